@@ -0,0 +1,41 @@
+//! Exit codes of wsinit and mkwsconfig, listed in the README so scripts can
+//! rely on them.
+
+use std::io;
+
+/// Anything not covered below.
+pub const FAILURE: i32 = 1;
+/// Bad command line arguments.
+pub const USAGE: i32 = 2;
+/// The profile, or another file asked for, does not exist.
+pub const NOT_FOUND: i32 = 3;
+/// A profile or settings file is corrupt, unsupported or unsafe.
+pub const INVALID: i32 = 4;
+/// Not allowed to read or write a file.
+pub const PERMISSION_DENIED: i32 = 5;
+/// A hook of the profile failed, its own status is in the error message.
+pub const HOOK_FAILED: i32 = 6;
+
+/// Code for an I/O error that has no more specific one.
+pub fn of_io(err: &io::Error) -> i32 {
+    match err.kind() {
+        io::ErrorKind::NotFound => NOT_FOUND,
+        io::ErrorKind::PermissionDenied => PERMISSION_DENIED,
+        io::ErrorKind::InvalidData => INVALID,
+        _ => FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_io_errors() {
+        let code = |kind| of_io(&io::Error::from(kind));
+        assert_eq!(code(io::ErrorKind::NotFound), NOT_FOUND);
+        assert_eq!(code(io::ErrorKind::PermissionDenied), PERMISSION_DENIED);
+        assert_eq!(code(io::ErrorKind::InvalidData), INVALID);
+        assert_eq!(code(io::ErrorKind::AlreadyExists), FAILURE);
+    }
+}
@@ -0,0 +1,19 @@
+//! Feed arbitrary bytes to the profile decoder. It may refuse them, but must
+//! not panic, hang or allocate without bounds.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use profile::{format, limits::Limits};
+
+fuzz_target!(|data: &[u8]| {
+    let limits = Limits {
+        max_size: 1 << 20,
+        ..Default::default()
+    };
+    if let Ok(profile) = format::decode_with_limits(data, &limits) {
+        // Whatever decodes has to encode and decode again.
+        let buf = format::encode(&profile).unwrap();
+        format::decode_with_limits(&buf, &limits).unwrap();
+    }
+});
@@ -1,18 +1,33 @@
 use std::{
-    fs::{create_dir_all, File},
+    fs::{canonicalize, create_dir_all, File},
     io::{self, stdin, Write},
+    path::{Path, PathBuf},
     process::exit,
 };
 
-use clap::{command, Parser};
-use paths::{get_os_config_dir, get_os_dir_sep};
+use clap::Parser;
+use paths::{get_profile_file_name, resolve_config_dir};
 
-use profile::*;
+use profile::{
+    capture::{CaptureOptions, Links},
+    meta::{ProfileMeta, META_FILE_NAME},
+    *,
+};
+use settings::{
+    roots::{ProfileRoot, Roots, USER_ROOT},
+    Settings,
+};
 
-/// Save current dir as a profile file.
+/// Save a dir as a profile file.
 #[derive(Parser, Debug)]
 #[command(author = "lI15SO0", version, about)]
 struct Args {
+    /// Dirs to capture. (default: ./)
+    /// With several dirs, or NAME=DIR, each one goes into a subdir of the
+    /// profile, named after the dir or NAME.
+    #[arg(value_name = "[NAME=]DIR")]
+    sources: Vec<String>,
+
     /// Profile name
     #[arg(short, long)]
     name: Option<String>,
@@ -24,15 +39,55 @@ struct Args {
     /// Allow empty files.
     #[arg(short, long)]
     raw: bool,
+
+    /// Leave out empty dirs, unless they have a .gitkeep or .keep file.
+    #[arg(long)]
+    no_empty_dirs: bool,
+
+    /// Replace the current dir name in names and contents with {{project_name}}.
+    #[arg(short, long)]
+    templatize: bool,
+
+    /// Skip paths matching this gitignore style glob. Can be repeated.
+    #[arg(short, long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Capture paths matching this glob even if ignored. Can be repeated.
+    #[arg(short, long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Don't honor .gitignore files.
+    #[arg(long)]
+    no_gitignore: bool,
+
+    /// What to do with symbolic links: store, follow or skip.
+    #[arg(long, value_name = "HOW", default_value_t = Links::Store)]
+    links: Links,
+
+    /// Capture links pointing outside of the captured dir.
+    #[arg(long)]
+    allow_external_links: bool,
+
+    /// Where profiles are kept, instead of $WSINIT_HOME or the default config
+    /// dir.
+    #[arg(long, value_name = "DIR")]
+    config_dir: Option<PathBuf>,
+
+    /// Profile root to save into, one of the profile_roots in the settings
+    /// that is not read-only. (default: user)
+    #[arg(long, value_name = "NAME")]
+    root: Option<String>,
 }
 
-const PROFILES_DIR_NAME: &str = "profiles";
-const PROFILE_SUFFIX: &str = ".bincode";
+const SETTING_NAME: &str = "settings.toml";
 
 fn main() {
     let args = Args::parse();
+    let config_dir = resolve_config_dir(args.config_dir.clone()).unwrap_or_else(|err| {
+        eprintln!("E: {}", err);
+        exit(err.exit_code());
+    });
 
-    let path = "./".to_string();
     let name = args.name.unwrap_or_else(|| {
         let mut line = String::new();
         print!("profile file name: ");
@@ -41,45 +96,205 @@ fn main() {
         line.trim().to_owned()
     });
 
-    let profile_target =
-        get_os_config_dir() + PROFILES_DIR_NAME + &get_os_dir_sep() + &name + PROFILE_SUFFIX;
+    let roots = profile_roots(&config_dir);
+    let root = target_root(&roots, args.root.as_deref());
+    let profile_target = root.path.join(get_profile_file_name(&name));
 
-    let profile = match args.raw {
-        true => DirRoot::from_dir_raw(path).unwrap_or_else(|err| {
-            // TODO: Maybe not friendly.
-            eprintln!("E: Failed to get dir infomations. cause: {}", err);
-            exit(1);
-        }),
-        false => DirRoot::from_dir(path).unwrap_or_else(|err| {
-            eprintln!("E: Failed to get dir infomations. cause: {}", err);
-            exit(1);
-        }),
+    let opts = CaptureOptions {
+        keep_empty_files: args.raw,
+        keep_empty_dirs: !args.no_empty_dirs,
+        use_gitignore: !args.no_gitignore,
+        exclude: args.exclude,
+        include: args.include,
+        links: args.links,
+        allow_external_links: args.allow_external_links,
     };
 
-    match ensure_dirs() {
-        Ok(_) => {}
-        Err(err) => {
-            eprintln!("E: Failed to init config dir: {}", err);
-            exit(1)
+    let sources: Vec<(Option<String>, String)> = match args.sources.is_empty() {
+        true => vec![(None, "./".to_string())],
+        false => args.sources.iter().map(|s| parse_source(s)).collect(),
+    };
+
+    let profile = match &sources[..] {
+        [(None, path)] => capture_source(path, &opts, args.templatize),
+        _ => {
+            let mut merged = Profile::new(DirRoot::new(""));
+            for (name, path) in sources {
+                let name = name.unwrap_or_else(|| dir_name(&path));
+                if let Err(err) = profile::name::check(&name) {
+                    eprintln!("E: Can't name a subdir \"{}\": {}", name, err);
+                    exit(exit_code::USAGE);
+                }
+                if merged.root.dirs.iter().any(|d| d.name == name) {
+                    eprintln!("E: Two sources are named \"{}\", use NAME=DIR.", name);
+                    exit(exit_code::USAGE);
+                }
+
+                let mut profile = capture_source(&path, &opts, args.templatize);
+                profile.meta.nest(&name);
+                profile.root.name = name;
+                merged.root.dirs.push(profile.root);
+                merged.meta.merge(profile.meta).unwrap_or_else(|err| {
+                    eprintln!("E: Failed to merge {} of {}: {}", META_FILE_NAME, path, err);
+                    exit(exit_code::INVALID);
+                });
+            }
+            merged
         }
     };
 
+    if root.name == USER_ROOT {
+        match ensure_dirs(&root.path) {
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("E: Failed to init config dir: {}", err);
+                exit(exit_code::of_io(&err))
+            }
+        };
+    }
+
     is_exists(&profile_target, args.force);
+    let shadowing = roots
+        .iter()
+        .take_while(|r| r.name != root.name)
+        .find(|r| r.path.join(get_profile_file_name(&name)).is_file());
+    if let Some(by) = shadowing {
+        eprintln!(
+            "W: The profile {} in root {} shadows the one saved into {}.",
+            name, by.name, root.name
+        );
+    }
     match profile.save_as(&profile_target) {
-        Ok(_) => println!("Saved successfully: {}", profile_target),
-        Err(err) => eprintln!("E: Failed to save profile file: {}", err),
+        Ok(_) => println!("Saved successfully: {}", profile_target.display()),
+        Err(err) => {
+            eprintln!("E: Failed to save profile file: {}", err);
+            exit(err.exit_code());
+        }
     };
 }
 
-fn ensure_dirs() -> Result<(), std::io::Error> {
-    let p = get_os_config_dir() + PROFILES_DIR_NAME;
-    if File::open(&p).is_err() {
-        let _ = create_dir_all(p)?;
+/// Split `NAME=DIR` into its parts. A plain `DIR` has no name.
+fn parse_source(s: &str) -> (Option<String>, String) {
+    match s.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !Path::new(s).exists() => {
+            (Some(name.to_string()), path.to_string())
+        }
+        _ => (None, s.to_string()),
+    }
+}
+
+fn capture_source(path: &str, opts: &CaptureOptions, templatize_name: bool) -> Profile {
+    if !Path::new(path).is_dir() {
+        eprintln!("E: {} is not a dir.", path);
+        exit(exit_code::NOT_FOUND);
+    }
+
+    let root = DirRoot::capture(path.to_string(), opts).unwrap_or_else(|err| {
+        // TODO: Maybe not friendly.
+        eprintln!("E: Failed to get dir infomations. cause: {}", err);
+        exit(exit_code::of_io(&err));
+    });
+
+    let mut profile = take_meta(root);
+    if templatize_name {
+        templatize(&mut profile.root, path);
+    }
+    profile
+}
+
+fn dir_name(path: &str) -> String {
+    canonicalize(path)
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+        .unwrap_or_else(|| {
+            eprintln!("E: Failed to get the name of {}.", path);
+            exit(exit_code::FAILURE);
+        })
+}
+
+/// Turn the name of the captured dir, in any case, into `{{project_name}}`.
+fn templatize(root: &mut DirRoot, path: &str) {
+    let name = dir_name(path);
+
+    let count = root.templatize(&name, "project_name");
+    println!(
+        "Replaced {} occurrences of \"{}\" with {{{{project_name}}}}.",
+        count, name
+    );
+}
+
+/// Move a top level `wsinit.toml` out of the tree and into the profile
+/// metadata.
+fn take_meta(mut root: DirRoot) -> Profile {
+    let meta = match root.files.iter().position(|f| f.name == META_FILE_NAME) {
+        Some(i) => root.files.remove(i),
+        None => return Profile::new(root),
+    };
+
+    let meta = match &meta.content {
+        FileContent::Text(s) => ProfileMeta::from_toml(s),
+        FileContent::Bin(_) | FileContent::Link(_) => Err("not a text file".to_string()),
+    }
+    .unwrap_or_else(|err| {
+        eprintln!("E: Invalid {}: {}", META_FILE_NAME, err);
+        exit(exit_code::INVALID);
+    });
+
+    let mut profile = Profile::new(root);
+    profile.meta = meta;
+    profile
+}
+
+/// Profile roots of the settings in `config_dir`, only the user store if
+/// there are no settings.
+fn profile_roots(config_dir: &Path) -> Roots {
+    let settings = match Settings::read_from(config_dir.join(SETTING_NAME)) {
+        Ok(it) => it,
+        Err(settings::Error::NotFound(_)) => Settings::new(),
+        Err(err) => {
+            eprintln!("W: Error in reading settings cause: {}", err);
+            Settings::new()
+        }
+    };
+    settings.roots(config_dir).unwrap_or_else(|err| {
+        eprintln!("E: {}", err);
+        exit(err.exit_code());
+    })
+}
+
+/// The root called `name`, the user store without one. Ends the process if
+/// there is no such root or it is read-only.
+fn target_root<'a>(roots: &'a Roots, name: Option<&str>) -> &'a ProfileRoot {
+    let root = match name {
+        None => return roots.user(),
+        Some(name) => roots.get(name).unwrap_or_else(|| {
+            eprintln!("E: No profile root is named {}.", name);
+            exit(exit_code::USAGE);
+        }),
+    };
+    if root.read_only {
+        eprintln!("E: Profile root {} is read-only.", root.name);
+        exit(exit_code::PERMISSION_DENIED);
+    }
+    if !root.path.is_dir() {
+        eprintln!(
+            "E: Profile root {} ({}) does not exist.",
+            root.name,
+            root.path.display()
+        );
+        exit(exit_code::NOT_FOUND);
+    }
+    root
+}
+
+fn ensure_dirs(p: &Path) -> Result<(), std::io::Error> {
+    if File::open(p).is_err() {
+        create_dir_all(p)?;
     }
     Ok(())
 }
 
-fn is_exists(path: &str, force: bool) {
+fn is_exists(path: &Path, force: bool) {
     if !force && File::open(path).is_ok() {
         print!("Profile file exists, Write anyway? (Y/N): ");
         let _ = io::stdout().flush();
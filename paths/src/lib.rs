@@ -1,9 +1,88 @@
-use std::env::consts::OS;
+use std::{
+    env::{self, consts::OS},
+    ffi::OsString,
+    fmt,
+    path::{Path, PathBuf},
+};
 
-pub fn get_os_config_dir() -> String {
-    match OS {
-        "windows" => std::env::var("APPDATA").unwrap() + "\\workspace-configures\\",
-        _ => std::env::var("HOME").unwrap() + "/.config/workspace_configures/",
+/// Overrides where the config dir is, for isolated stores and containers.
+pub const HOME_ENV: &str = "WSINIT_HOME";
+
+const DIR_NAME: &str = "workspace_configures";
+
+#[derive(Debug)]
+pub enum Error {
+    /// None of the environment variables the config dir is found by is set.
+    MissingEnv(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingEnv(var) => write!(
+                f,
+                "{} is not set, can't find the config dir (set {} or use --config-dir)",
+                var, HOME_ENV
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        exit_code::FAILURE
+    }
+}
+
+/// The config dir given on the command line, or else the one from
+/// [`get_os_config_dir`].
+pub fn resolve_config_dir(flag: Option<PathBuf>) -> Result<PathBuf, Error> {
+    match flag {
+        Some(it) => Ok(it),
+        None => get_os_config_dir(),
+    }
+}
+
+/// Where profiles and settings are kept, the first of:
+///
+/// 1. `$WSINIT_HOME`;
+/// 2. `$XDG_CONFIG_HOME/workspace_configures`, except on Windows;
+/// 3. `$HOME/.config/workspace_configures`, or
+///    `%APPDATA%\workspace-configures` on Windows.
+///
+/// Empty variables count as unset, and so does a relative `XDG_CONFIG_HOME`,
+/// as the XDG spec asks. A store already in the `$HOME` location keeps being
+/// used while the `XDG_CONFIG_HOME` one does not exist.
+pub fn get_os_config_dir() -> Result<PathBuf, Error> {
+    config_dir_from(OS, |var| env::var_os(var), Path::is_dir)
+}
+
+fn config_dir_from(
+    os: &str,
+    var: impl Fn(&str) -> Option<OsString>,
+    is_dir: impl Fn(&Path) -> bool,
+) -> Result<PathBuf, Error> {
+    let var = |name: &str| var(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    if let Some(home) = var(HOME_ENV) {
+        return Ok(home);
+    }
+    if os == "windows" {
+        return match var("APPDATA") {
+            Some(it) => Ok(it.join("workspace-configures")),
+            None => Err(Error::MissingEnv("APPDATA")),
+        };
+    }
+
+    let home = var("HOME").map(|h| h.join(".config").join(DIR_NAME));
+    let xdg = var("XDG_CONFIG_HOME")
+        .filter(|x| x.is_absolute())
+        .map(|x| x.join(DIR_NAME));
+    match (xdg, home) {
+        (Some(xdg), Some(home)) if !is_dir(&xdg) && is_dir(&home) => Ok(home),
+        (Some(it), _) | (None, Some(it)) => Ok(it),
+        (None, None) => Err(Error::MissingEnv("HOME")),
     }
 }
 
@@ -15,13 +94,72 @@ pub fn get_os_dir_sep() -> String {
     .to_string()
 }
 
-pub fn get_profile_path(profile_name: String) -> String {
+/// Where the profile called `profile_name` is stored under `config_dir`.
+pub fn get_profile_path(config_dir: &Path, profile_name: &str) -> PathBuf {
+    config_dir
+        .join("profiles")
+        .join(get_profile_file_name(profile_name))
+}
+
+/// File name of the profile called `profile_name`.
+pub fn get_profile_file_name(profile_name: &str) -> String {
     if profile_name.ends_with("bincode") {
-        get_os_config_dir() + "profiles" + &get_os_dir_sep() + &profile_name
+        profile_name.to_string()
     } else {
-        get_os_config_dir() + "profiles" + &get_os_dir_sep() + &profile_name + ".bincode"
+        profile_name.to_string() + ".bincode"
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    fn resolve(os: &str, vars: &[(&str, &str)], dirs: &[&str]) -> Result<PathBuf, Error> {
+        config_dir_from(
+            os,
+            |name| {
+                vars.iter()
+                    .find(|(k, _)| *k == name)
+                    .map(|(_, v)| OsString::from(v))
+            },
+            |path| dirs.iter().any(|d| Path::new(d) == path),
+        )
+    }
+
+    #[test]
+    fn finds_config_dir() {
+        let home = [("HOME", "/home/u")];
+        let xdg = [("HOME", "/home/u"), ("XDG_CONFIG_HOME", "/xdg")];
+        assert_eq!(
+            resolve("linux", &home, &[]).unwrap(),
+            Path::new("/home/u/.config/workspace_configures")
+        );
+        assert_eq!(
+            resolve("linux", &xdg, &[]).unwrap(),
+            Path::new("/xdg/workspace_configures")
+        );
+        assert_eq!(
+            resolve("linux", &[("XDG_CONFIG_HOME", "rel"), home[0]], &[]).unwrap(),
+            Path::new("/home/u/.config/workspace_configures")
+        );
+        assert_eq!(
+            resolve("linux", &[(HOME_ENV, "/store"), xdg[1]], &[]).unwrap(),
+            Path::new("/store")
+        );
+        assert!(matches!(
+            resolve("linux", &[("HOME", "")], &[]),
+            Err(Error::MissingEnv("HOME"))
+        ));
+    }
+
+    #[test]
+    fn keeps_existing_store() {
+        let xdg = [("HOME", "/home/u"), ("XDG_CONFIG_HOME", "/xdg")];
+        let old = "/home/u/.config/workspace_configures";
+        assert_eq!(resolve("linux", &xdg, &[old]).unwrap(), Path::new(old));
+        assert_eq!(
+            resolve("linux", &xdg, &[old, "/xdg/workspace_configures"]).unwrap(),
+            Path::new("/xdg/workspace_configures")
+        );
+    }
+}
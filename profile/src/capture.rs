@@ -0,0 +1,439 @@
+//! Options and ignore rules used when capturing a dir into a profile.
+//!
+//! A path is skipped when, in this order:
+//!
+//! 1. it matches an `include` glob: it is captured, whatever else says;
+//! 2. it matches an `exclude` glob, is VCS metadata (`.git`, `.hg`, `.svn`)
+//!    or the records wsinit keeps in a workspace (`.wsinit`);
+//! 3. the closest `.wsinitignore` or `.gitignore` with an opinion ignores it.
+//!
+//! Ignore files use gitignore syntax and apply to the dir they are in and
+//! everything below it. Within one dir `.wsinitignore` is read after
+//! `.gitignore`, so it can re-include files git ignores with `!pattern`.
+//! Ignored dirs are not walked into, so to capture something under an
+//! ignored dir, include the dir itself.
+//!
+//! Empty files are left out, empty dirs are kept. A dir with a `.gitkeep` or
+//! `.keep` file in it is never empty, even if the marker itself is.
+//!
+//! Symbolic links are stored as links by default. A link that points outside
+//! of the captured dir, or to an absolute path, would not mean the same thing
+//! in another workspace and fails the capture unless allowed explicitly.
+
+use std::{
+    fmt, fs, io,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+
+/// Profile specific ignore file, never captured itself.
+pub const IGNORE_FILE_NAME: &str = ".wsinitignore";
+
+const GIT_IGNORE_FILE_NAME: &str = ".gitignore";
+
+/// Skipped unless included explicitly.
+const DEFAULT_EXCLUDES: [&str; 4] = [".git", ".hg", ".svn", ".wsinit"];
+
+/// Files that keep their dir in the profile, captured even when empty.
+pub const KEEP_MARKERS: [&str; 2] = [".gitkeep", ".keep"];
+
+/// What to do with symbolic links.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Links {
+    /// Store the link itself, it is recreated as a link.
+    #[default]
+    Store,
+    /// Store what the link points to, as if it was there.
+    Follow,
+    /// Leave links out.
+    Skip,
+}
+
+impl Links {
+    pub const ALL: [Links; 3] = [Links::Store, Links::Follow, Links::Skip];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Links::Store => "store",
+            Links::Follow => "follow",
+            Links::Skip => "skip",
+        }
+    }
+}
+
+impl fmt::Display for Links {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Links {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|l| l.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|l| l.name()).collect();
+                format!("expected one of: {}", names.join(", "))
+            })
+    }
+}
+
+pub struct CaptureOptions {
+    /// Keep empty files, not only empty keep markers.
+    pub keep_empty_files: bool,
+    /// Keep dirs with nothing captured in them.
+    pub keep_empty_dirs: bool,
+    /// Honor `.gitignore` files, `.wsinitignore` is always honored.
+    pub use_gitignore: bool,
+    /// Extra gitignore style globs to skip.
+    pub exclude: Vec<String>,
+    /// Gitignore style globs to capture even if something else ignores them.
+    pub include: Vec<String>,
+    pub links: Links,
+    /// Capture links pointing outside of the captured dir.
+    pub allow_external_links: bool,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            keep_empty_files: false,
+            keep_empty_dirs: true,
+            use_gitignore: true,
+            exclude: vec![],
+            include: vec![],
+            links: Links::default(),
+            allow_external_links: false,
+        }
+    }
+}
+
+/// Ignore rules in effect while walking the captured dir.
+pub(crate) struct Rules {
+    root: PathBuf,
+    use_gitignore: bool,
+    include: Gitignore,
+    exclude: Gitignore,
+    /// One matcher per dir from the root down to the one being walked.
+    stack: Vec<Gitignore>,
+    /// Real paths of the dirs being walked, to catch followed links that
+    /// lead back up.
+    walking: Vec<PathBuf>,
+}
+
+impl Rules {
+    pub(crate) fn new(root: &Path, opts: &CaptureOptions) -> Result<Self, io::Error> {
+        let globs = |lines: &mut dyn Iterator<Item = &str>| {
+            let mut builder = GitignoreBuilder::new(root);
+            for line in lines {
+                if let Err(err) = builder.add_line(None, line) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+                }
+            }
+            builder
+                .build()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+        };
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            use_gitignore: opts.use_gitignore,
+            include: globs(&mut opts.include.iter().map(String::as_str))?,
+            exclude: globs(
+                &mut DEFAULT_EXCLUDES
+                    .into_iter()
+                    .chain(opts.exclude.iter().map(String::as_str)),
+            )?,
+            stack: vec![],
+            walking: vec![],
+        })
+    }
+
+    /// Load the ignore files of `dir` before walking into it.
+    pub(crate) fn enter(&mut self, dir: &Path) -> Result<(), io::Error> {
+        let real = fs::canonicalize(dir)?;
+        if self.walking.contains(&real) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: links lead back into a dir above it", dir.display()),
+            ));
+        }
+
+        let mut builder = GitignoreBuilder::new(dir);
+        let mut files: Vec<PathBuf> = vec![];
+        if self.use_gitignore {
+            files.push(dir.join(GIT_IGNORE_FILE_NAME));
+        }
+        files.push(dir.join(IGNORE_FILE_NAME));
+
+        for f in files.into_iter().filter(|f| f.is_file()) {
+            if let Some(err) = builder.add(&f) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", f.display(), err),
+                ));
+            }
+        }
+
+        match builder.build() {
+            Ok(it) => self.stack.push(it),
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        }
+        self.walking.push(real);
+        Ok(())
+    }
+
+    pub(crate) fn leave(&mut self) {
+        self.stack.pop();
+        self.walking.pop();
+    }
+
+    /// Fail unless the link at `path` pointing to `target` stays inside the
+    /// captured dir, or `opts` allows it not to.
+    pub(crate) fn check_link(
+        &self,
+        path: &Path,
+        target: &Path,
+        opts: &CaptureOptions,
+    ) -> Result<(), io::Error> {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let dir = dir.strip_prefix(&self.root).unwrap_or(dir);
+        if opts.allow_external_links || !escapes(dir, target) {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} points outside of the captured dir, to {}",
+                path.display(),
+                target.display()
+            ),
+        ))
+    }
+
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.include.matched(path, is_dir).is_ignore() {
+            return false;
+        }
+        if path.file_name().is_some_and(|n| n == IGNORE_FILE_NAME) && !is_dir {
+            return true;
+        }
+        if self.exclude.matched(path, is_dir).is_ignore() {
+            return true;
+        }
+        for rules in self.stack.iter().rev() {
+            match rules.matched(path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+            }
+        }
+        false
+    }
+}
+
+/// Whether `target`, of a link in `dir` relative to the captured dir, leads
+/// out of it. Absolute targets always do.
+pub(crate) fn escapes(dir: &Path, target: &Path) -> bool {
+    let mut depth = dir
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .count();
+    for c in target.components() {
+        match c {
+            Component::Normal(_) => depth += 1,
+            Component::ParentDir if depth == 0 => return true,
+            Component::ParentDir => depth -= 1,
+            Component::RootDir | Component::Prefix(_) => return true,
+            Component::CurDir => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DirRoot;
+
+    /// A temp dir with `entries`, dirs end with `/`.
+    fn tree(entries: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, content) in entries {
+            let path = dir.path().join(path);
+            match path.to_string_lossy().ends_with('/') {
+                true => fs::create_dir_all(&path).unwrap(),
+                false => {
+                    fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    fs::write(&path, content).unwrap();
+                }
+            }
+        }
+        dir
+    }
+
+    /// Paths captured from `dir`, dirs end with `/`.
+    fn capture(dir: &tempfile::TempDir, opts: &CaptureOptions) -> Vec<String> {
+        fn walk(root: &DirRoot, prefix: &str, found: &mut Vec<String>) {
+            for d in &root.dirs {
+                let path = format!("{}{}/", prefix, d.name);
+                found.push(path.clone());
+                walk(d, &path, found);
+            }
+            for f in &root.files {
+                found.push(format!("{}{}", prefix, f.name));
+            }
+        }
+        let root = DirRoot::capture(dir.path().display().to_string(), opts).unwrap();
+        let mut found = vec![];
+        walk(&root, "", &mut found);
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn honors_ignore_files() {
+        let dir = tree(&[
+            (".git/config", "x"),
+            (".gitignore", "*.log\nbuild/\n"),
+            (".wsinitignore", "!keep.log\nsecret.txt\n"),
+            ("a.log", "x"),
+            ("keep.log", "x"),
+            ("secret.txt", "x"),
+            ("build/out", "x"),
+            ("src/main.rs", "x"),
+            ("src/.gitignore", "gen.rs\n"),
+            ("src/gen.rs", "x"),
+        ]);
+        assert_eq!(
+            capture(&dir, &CaptureOptions::default()),
+            [
+                ".gitignore",
+                "keep.log",
+                "src/",
+                "src/.gitignore",
+                "src/main.rs"
+            ]
+        );
+
+        let opts = CaptureOptions {
+            use_gitignore: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            capture(&dir, &opts),
+            [
+                ".gitignore",
+                "a.log",
+                "build/",
+                "build/out",
+                "keep.log",
+                "src/",
+                "src/.gitignore",
+                "src/gen.rs",
+                "src/main.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn exclude_and_include_globs() {
+        let dir = tree(&[
+            (".git/config", "x"),
+            (".gitignore", "deps/\n"),
+            ("deps/lib.rs", "x"),
+            ("docs/a.md", "x"),
+            ("docs/b.md", "x"),
+        ]);
+        let opts = CaptureOptions {
+            exclude: vec!["docs/".to_string(), ".gitignore".to_string()],
+            include: vec!["docs/a.md".to_string(), ".git".to_string()],
+            ..Default::default()
+        };
+        // docs/ is not walked into, so including a file in it is not enough.
+        assert_eq!(capture(&dir, &opts), [".git/", ".git/config"]);
+
+        let opts = CaptureOptions {
+            include: vec!["deps".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            capture(&dir, &opts),
+            [
+                ".gitignore",
+                "deps/",
+                "deps/lib.rs",
+                "docs/",
+                "docs/a.md",
+                "docs/b.md"
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_empty_dirs_not_empty_files() {
+        let dir = tree(&[
+            ("logs/", ""),
+            ("data/raw/", ""),
+            ("cache/.gitkeep", ""),
+            ("tmp/.keep", ""),
+            ("empty.txt", ""),
+            ("src/main.rs", "x"),
+        ]);
+        assert_eq!(
+            capture(&dir, &CaptureOptions::default()),
+            [
+                "cache/",
+                "cache/.gitkeep",
+                "data/",
+                "data/raw/",
+                "logs/",
+                "src/",
+                "src/main.rs",
+                "tmp/",
+                "tmp/.keep"
+            ]
+        );
+
+        let opts = CaptureOptions {
+            keep_empty_dirs: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            capture(&dir, &opts),
+            [
+                "cache/",
+                "cache/.gitkeep",
+                "src/",
+                "src/main.rs",
+                "tmp/",
+                "tmp/.keep"
+            ]
+        );
+
+        let opts = CaptureOptions {
+            keep_empty_files: true,
+            keep_empty_dirs: false,
+            ..Default::default()
+        };
+        assert!(capture(&dir, &opts).contains(&"empty.txt".to_string()));
+    }
+
+    #[test]
+    fn links_leading_out_escape() {
+        assert!(!escapes(Path::new(""), Path::new("AGENTS.md")));
+        assert!(!escapes(Path::new("a/b"), Path::new("../../c")));
+        assert!(!escapes(Path::new("a"), Path::new("./b/../c")));
+        assert!(escapes(Path::new(""), Path::new("../shared/.envrc")));
+        assert!(escapes(Path::new("a"), Path::new("b/../../../c")));
+        assert!(escapes(Path::new("a"), Path::new("/etc/passwd")));
+    }
+}
@@ -0,0 +1,154 @@
+//! Errors reading and writing profiles.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use crate::name::NameError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// No profile file at this path.
+    NotFound(PathBuf),
+    PermissionDenied {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// Not a profile, or a damaged one.
+    Corrupt(bincode::Error),
+    /// Written by a newer wsinit, with a format version or features this one
+    /// does not know.
+    Unsupported(String),
+    /// The profile metadata is not valid.
+    Meta(String),
+    /// A name in the tree that can't be used as a file or dir name.
+    Name {
+        /// `/` separated path of the entry, up to and including the name.
+        path: String,
+        error: NameError,
+    },
+    /// A link pointing outside of the dir the profile is built into.
+    Link {
+        /// `/` separated path of the link.
+        path: String,
+        target: String,
+    },
+    /// The profile goes beyond the [`Limits`](crate::limits::Limits).
+    TooLarge {
+        what: &'static str,
+        max: u64,
+    },
+    /// The profile could not be serialized.
+    Encode(bincode::Error),
+}
+
+impl Error {
+    /// Sort an I/O error on `path` into the variant for it.
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        let path = path.to_path_buf();
+        match source.kind() {
+            io::ErrorKind::NotFound => Error::NotFound(path),
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied { path, source },
+            _ => Error::Io { path, source },
+        }
+    }
+
+    /// Process exit code for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::NotFound(_) => exit_code::NOT_FOUND,
+            Error::PermissionDenied { .. } => exit_code::PERMISSION_DENIED,
+            Error::Io { .. } | Error::Encode(_) => exit_code::FAILURE,
+            Error::Corrupt(_)
+            | Error::Unsupported(_)
+            | Error::Meta(_)
+            | Error::Name { .. }
+            | Error::Link { .. }
+            | Error::TooLarge { .. } => exit_code::INVALID,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(path) => write!(f, "{}: no such profile", path.display()),
+            Error::PermissionDenied { path, .. } => {
+                write!(f, "{}: permission denied", path.display())
+            }
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Corrupt(err) => write!(f, "corrupt profile: {}", err),
+            Error::Unsupported(msg) => f.write_str(msg),
+            Error::Meta(msg) => write!(f, "invalid metadata: {}", msg),
+            Error::Name { path, error } => write!(f, "unsafe name {:?}: {}", path, error),
+            Error::Link { path, target } => write!(
+                f,
+                "link {:?} points outside of the target dir, to {:?}",
+                path, target
+            ),
+            Error::TooLarge { what, max } => {
+                write!(f, "profile has more {} than the limit of {}", what, max)
+            }
+            Error::Encode(err) => write!(f, "failed to encode profile: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::PermissionDenied { source, .. } | Error::Io { source, .. } => Some(source),
+            Error::Corrupt(err) | Error::Encode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_io_errors_into_variants() {
+        let path = Path::new("rust");
+        let error = |kind| Error::io(path, io::Error::from(kind));
+
+        let err = error(io::ErrorKind::NotFound);
+        assert!(matches!(&err, Error::NotFound(p) if p == path));
+        assert_eq!(err.exit_code(), exit_code::NOT_FOUND);
+        let err = error(io::ErrorKind::PermissionDenied);
+        assert!(matches!(err, Error::PermissionDenied { .. }));
+        assert_eq!(err.exit_code(), exit_code::PERMISSION_DENIED);
+        let err = error(io::ErrorKind::UnexpectedEof);
+        assert!(matches!(err, Error::Io { .. }));
+        assert_eq!(err.exit_code(), exit_code::FAILURE);
+    }
+
+    #[test]
+    fn bad_profiles_are_invalid() {
+        let errors = [
+            Error::Unsupported("format version 9".to_string()),
+            Error::Meta("bad".to_string()),
+            Error::Name {
+                path: "..".to_string(),
+                error: NameError::Dots,
+            },
+            Error::Link {
+                path: "a".to_string(),
+                target: "/etc".to_string(),
+            },
+            Error::TooLarge {
+                what: "entries",
+                max: 1,
+            },
+        ];
+        for err in errors {
+            assert_eq!(err.exit_code(), exit_code::INVALID, "{}", err);
+        }
+    }
+}
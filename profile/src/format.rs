@@ -0,0 +1,425 @@
+//! On-disk container for profile files.
+//!
+//! ```text
+//! +---------+-----------+-----------+-------------------+
+//! | "WSIP"  | version   | flags     | bincode payload   |
+//! | 4 bytes | u16 (LE)  | u32 (LE)  |                   |
+//! +---------+-----------+-----------+-------------------+
+//! ```
+//!
+//! The payload is the optional sections announced by the flags, in flag
+//! order, followed by the `DirRoot` tree.
+//!
+//! Profiles written by 0.1.x have no header at all, they are a bare
+//! `bincode::serialize(&DirRoot)`. [`decode`] still accepts them and upgrades
+//! them in memory, so old profiles keep working without being re-captured.
+//!
+//! Versions:
+//!
+//! 1. The 0.1.x tree behind the header.
+//! 2. Files carry their Unix permission bits.
+//! 3. Symbolic links, as a file type of their own.
+
+use bincode::Options;
+
+use crate::{
+    legacy,
+    limits::{self, Limits},
+    meta::ProfileMeta,
+    DirRoot, Error, Profile,
+};
+
+pub const MAGIC: &[u8; 4] = b"WSIP";
+
+/// Version of the payload layout. Bump it whenever `DirRoot` or anything it
+/// contains changes shape, and teach [`decode`] how to upgrade the old one.
+pub const FORMAT_VERSION: u16 = 3;
+
+/// The payload starts with the profile metadata as a TOML string.
+pub const FLAG_META: u32 = 1 << 0;
+
+/// Feature flags this build understands. Profiles carrying any other flag
+/// were written by a newer wsinit and are rejected rather than misread.
+pub const KNOWN_FLAGS: u32 = FLAG_META;
+
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u16,
+    pub flags: u32,
+}
+
+impl Header {
+    pub fn current() -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            flags: 0,
+        }
+    }
+
+    /// Parse the header at the start of `buf`.
+    /// Returns `None` for headerless (0.1.x) profiles.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < HEADER_LEN || &buf[..MAGIC.len()] != MAGIC {
+            return None;
+        }
+        let version = u16::from_le_bytes([buf[4], buf[5]]);
+        let flags = u32::from_le_bytes([buf[6], buf[7], buf[8], buf[9]]);
+        Some(Self { version, flags })
+    }
+
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(&self.flags.to_le_bytes());
+    }
+}
+
+pub fn encode(profile: &Profile) -> Result<Vec<u8>, Error> {
+    let mut header = Header::current();
+    let meta = match profile.meta.is_empty() {
+        true => None,
+        false => {
+            header.flags |= FLAG_META;
+            Some(profile.meta.to_toml().map_err(Error::Meta)?)
+        }
+    };
+
+    let mut buf = Vec::with_capacity(HEADER_LEN);
+    header.write_to(&mut buf);
+    if let Some(meta) = meta {
+        bincode::serialize_into(&mut buf, &meta).map_err(Error::Encode)?;
+    }
+    bincode::serialize_into(&mut buf, &profile.root).map_err(Error::Encode)?;
+    Ok(buf)
+}
+
+/// Read a profile, refusing it if any name in it is unsafe to build, or a
+/// link in it points outside of the target dir.
+pub fn decode(buf: &[u8]) -> Result<Profile, Error> {
+    decode_with_limits(buf, &Limits::default())
+}
+
+/// Like [`decode`], refusing profiles beyond `limits`.
+pub fn decode_with_limits(buf: &[u8], limits: &Limits) -> Result<Profile, Error> {
+    if buf.len() as u64 > limits.max_size {
+        return Err(Error::TooLarge {
+            what: "bytes",
+            max: limits.max_size,
+        });
+    }
+    let profile = limits::bounded(limits, || read(buf))?;
+    profile.root.check_names()?;
+    if !limits.allow_external_links {
+        profile.root.check_links()?;
+    }
+    Ok(profile)
+}
+
+fn read(buf: &[u8]) -> Result<Profile, Error> {
+    let header = match Header::parse(buf) {
+        Some(it) => it,
+        None => {
+            let root = legacy::decode_v0(buf).map_err(Error::Corrupt)?;
+            return Ok(Profile::new(root));
+        }
+    };
+
+    if header.flags & !KNOWN_FLAGS != 0 {
+        return Err(Error::Unsupported(format!(
+            "profile uses unsupported features (flags {:#x}), please upgrade wsinit",
+            header.flags & !KNOWN_FLAGS
+        )));
+    }
+
+    if header.version == 0 || header.version > FORMAT_VERSION {
+        return Err(Error::Unsupported(format!(
+            "profile format version {} is not supported (newest known: {})",
+            header.version, FORMAT_VERSION
+        )));
+    }
+
+    let mut payload = &buf[HEADER_LEN..];
+    let meta = match header.flags & FLAG_META != 0 {
+        false => ProfileMeta::default(),
+        true => match limits::options(payload.len()).deserialize_from::<_, String>(&mut payload) {
+            Ok(it) => ProfileMeta::from_toml(&it).map_err(Error::Meta)?,
+            Err(err) => return Err(Error::Corrupt(err)),
+        },
+    };
+    let root: DirRoot = match header.version {
+        1 => legacy::decode_v1(&mut payload),
+        2 => legacy::decode_v2(&mut payload),
+        _ => limits::options(payload.len()).deserialize_from(&mut payload),
+    }
+    .map_err(Error::Corrupt)?;
+
+    Ok(Profile { meta, root })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{name::NameError, FileContent, FileInfo, FileType};
+
+    fn sample() -> Profile {
+        Profile::new(sample_root())
+    }
+
+    fn sample_root() -> DirRoot {
+        let mut root = DirRoot::new("");
+        let mut src = DirRoot::new("src");
+        src.files.push(FileInfo::new(
+            "main.rs",
+            FileType::Text,
+            FileContent::Text("fn main() {}\n".to_string()),
+        ));
+        src.files[0].mode = Some(0o755);
+        root.dirs.push(src);
+        root.files.push(FileInfo::new(
+            "logo.bin",
+            FileType::Bin,
+            FileContent::Bin(vec![0xff, 0x00]),
+        ));
+        root
+    }
+
+    #[test]
+    fn round_trip() {
+        let buf = encode(&sample()).unwrap();
+        assert_eq!(Header::parse(&buf), Some(Header::current()));
+
+        let root = decode(&buf).unwrap().root;
+        assert_eq!(root.dirs[0].name, "src");
+        assert_eq!(root.dirs[0].files[0].name, "main.rs");
+        assert_eq!(root.dirs[0].files[0].mode, Some(0o755));
+        assert_eq!(root.files[0].name, "logo.bin");
+        assert_eq!(root.files[0].mode, None);
+    }
+
+    #[test]
+    fn reads_headerless_profiles() {
+        let buf = legacy::encode_v0(&sample_root()).unwrap();
+        assert_eq!(Header::parse(&buf), None);
+
+        let root = decode(&buf).unwrap().root;
+        assert_eq!(root.dirs[0].files[0].name, "main.rs");
+        assert_eq!(root.dirs[0].files[0].mode, None);
+    }
+
+    #[test]
+    fn headerless_profiles_have_no_links() {
+        let mut root = sample_root();
+        root.files.push(FileInfo::new(
+            "CLAUDE.md",
+            FileType::Link,
+            FileContent::Link("AGENTS.md".to_string()),
+        ));
+        assert!(legacy::encode_v0(&root).is_err());
+    }
+
+    #[test]
+    fn reads_version_1_profiles() {
+        let mut buf = vec![];
+        Header {
+            version: 1,
+            flags: 0,
+        }
+        .write_to(&mut buf);
+        buf.extend(legacy::encode_v0(&sample_root()).unwrap());
+
+        let root = decode(&buf).unwrap().root;
+        assert_eq!(root.dirs[0].files[0].name, "main.rs");
+        assert_eq!(root.dirs[0].files[0].mode, None);
+    }
+
+    #[test]
+    fn round_trip_with_links() {
+        let mut profile = sample();
+        profile.root.files.push(FileInfo::new(
+            "CLAUDE.md",
+            FileType::Link,
+            FileContent::Link("AGENTS.md".to_string()),
+        ));
+
+        let root = decode(&encode(&profile).unwrap()).unwrap().root;
+        assert!(matches!(&root.files[1].content, FileContent::Link(t) if t == "AGENTS.md"));
+    }
+
+    #[test]
+    fn rejects_links_leading_out() {
+        for target in ["/home/u/.ssh", "../../..", "../../.envrc"] {
+            let mut profile = sample();
+            profile.root.dirs[0].files.push(FileInfo::new(
+                "x",
+                FileType::Link,
+                FileContent::Link(target.to_string()),
+            ));
+            let buf = encode(&profile).unwrap();
+            assert!(matches!(
+                decode(&buf),
+                Err(Error::Link { path, target: t }) if path == "src/x" && t == target
+            ));
+
+            let limits = Limits {
+                allow_external_links: true,
+                ..Default::default()
+            };
+            assert!(decode_with_limits(&buf, &limits).is_ok());
+        }
+    }
+
+    #[test]
+    fn round_trip_with_meta() {
+        let mut profile = sample();
+        profile.meta = ProfileMeta::from_toml("[[params]]\nname = \"author\"\n").unwrap();
+
+        let buf = encode(&profile).unwrap();
+        assert_eq!(Header::parse(&buf).unwrap().flags, FLAG_META);
+
+        let profile = decode(&buf).unwrap();
+        assert_eq!(profile.meta.params[0].name, "author");
+        assert_eq!(profile.root.files[0].name, "logo.bin");
+    }
+
+    #[test]
+    fn rejects_unsafe_names() {
+        let mut profile = sample();
+        profile.root.dirs[0].files[0].name = "../../.bashrc".to_string();
+
+        let buf = encode(&profile).unwrap();
+        assert!(matches!(
+            decode(&buf),
+            Err(Error::Name { path, error: NameError::Separator }) if path == "src/../../.bashrc"
+        ));
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        let mut profile = sample();
+        profile.root.files.push(FileInfo::new(
+            "src",
+            FileType::Text,
+            FileContent::Text(String::new()),
+        ));
+        assert!(matches!(
+            decode(&encode(&profile).unwrap()),
+            Err(Error::Name { path, error: NameError::Duplicate }) if path == "src"
+        ));
+
+        let mut profile = sample();
+        let main = profile.root.dirs[0].files[0].clone();
+        profile.root.dirs[0].files.push(main);
+        assert!(matches!(
+            decode(&encode(&profile).unwrap()),
+            Err(Error::Name { path, error: NameError::Duplicate }) if path == "src/main.rs"
+        ));
+
+        let mut profile = sample();
+        profile.root.files.push(FileInfo::new(
+            "x",
+            FileType::Link,
+            FileContent::Link(String::new()),
+        ));
+        assert!(matches!(
+            decode(&encode(&profile).unwrap()),
+            Err(Error::Name {
+                error: NameError::LinkTarget,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn truncated_profiles_fail_cleanly() {
+        let mut profile = sample();
+        profile.meta = ProfileMeta::from_toml("version = \"1.0\"\n").unwrap();
+        let buf = encode(&profile).unwrap();
+        for len in 0..buf.len() {
+            assert!(decode(&buf[..len]).is_err(), "decoded {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn refuses_huge_length_prefixes() {
+        let mut buf = encode(&sample()).unwrap();
+        // Name of the root dir, claiming most of the address space.
+        buf[HEADER_LEN..HEADER_LEN + 8].copy_from_slice(&(u64::MAX >> 2).to_le_bytes());
+        assert!(matches!(decode(&buf), Err(Error::Corrupt(_))));
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let mut root = sample_root();
+        for _ in 0..10 {
+            let mut parent = DirRoot::new("");
+            root.name = "d".to_string();
+            parent.dirs.push(root);
+            root = parent;
+        }
+        let buf = encode(&Profile::new(root)).unwrap();
+        assert!(decode(&buf).is_ok());
+
+        let limits = Limits {
+            max_depth: 10,
+            ..Default::default()
+        };
+        assert!(matches!(
+            decode_with_limits(&buf, &limits),
+            Err(Error::TooLarge {
+                what: "levels of dirs",
+                max: 10
+            })
+        ));
+
+        let limits = Limits {
+            max_entries: 12,
+            ..Default::default()
+        };
+        assert!(matches!(
+            decode_with_limits(&buf, &limits),
+            Err(Error::TooLarge {
+                what: "files and dirs",
+                ..
+            })
+        ));
+
+        let limits = Limits {
+            max_size: buf.len() as u64 - 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            decode_with_limits(&buf, &limits),
+            Err(Error::TooLarge { what: "bytes", .. })
+        ));
+    }
+
+    #[test]
+    fn decodes_fuzz_seeds() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../fuzz/corpus/decode");
+        // One seed has a link leading out, refused after it is decoded.
+        let limits = Limits {
+            allow_external_links: true,
+            ..Default::default()
+        };
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let buf = std::fs::read(&path).unwrap();
+            if let Err(err) = decode_with_limits(&buf, &limits) {
+                panic!("{}: {}", path.display(), err);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_version_and_flags() {
+        let mut buf = encode(&sample()).unwrap();
+        buf[4] = 0xff;
+        assert!(decode(&buf).is_err());
+
+        let mut buf = encode(&sample()).unwrap();
+        buf[9] = 0x80;
+        assert!(decode(&buf).is_err());
+    }
+}
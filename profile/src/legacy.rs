@@ -0,0 +1,185 @@
+//! Frozen copies of older profile layouts.
+//!
+//! These types must never change: they describe bytes that already exist on
+//! users' disks. When the current model moves on, add a new module here and
+//! a conversion into the current types instead of editing an old one.
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::limits;
+
+/// Headerless layout written by 0.1.x.
+mod v0 {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct DirRoot {
+        pub name: String,
+        #[serde(deserialize_with = "crate::limits::dirs")]
+        pub dirs: Vec<DirRoot>,
+        #[serde(deserialize_with = "crate::limits::files")]
+        pub files: Vec<FileInfo>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct FileInfo {
+        pub name: String,
+        pub file_type: FileType,
+        pub content: FileContent,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum FileType {
+        Bin,
+        Text,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum FileContent {
+        Text(String),
+        Bin(Vec<u8>),
+    }
+}
+
+/// Tree of format version 1. It is the 0.1.x tree, only behind the header,
+/// so the `v0` types describe it.
+mod v1 {
+    pub use super::v0::DirRoot;
+}
+
+/// Tree of format version 2, files have permission bits.
+mod v2 {
+    use super::*;
+    pub use v0::{FileContent, FileType};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct DirRoot {
+        pub name: String,
+        #[serde(deserialize_with = "crate::limits::dirs")]
+        pub dirs: Vec<DirRoot>,
+        #[serde(deserialize_with = "crate::limits::files")]
+        pub files: Vec<FileInfo>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct FileInfo {
+        pub name: String,
+        pub file_type: FileType,
+        pub content: FileContent,
+        pub mode: Option<u32>,
+    }
+}
+
+pub fn decode_v0(buf: &[u8]) -> bincode::Result<crate::DirRoot> {
+    match limits::options(buf.len()).deserialize::<v0::DirRoot>(buf) {
+        Ok(it) => Ok(it.into()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Read the tree of a version 1 payload, which has no permission bits.
+pub fn decode_v1(payload: &mut &[u8]) -> bincode::Result<crate::DirRoot> {
+    match limits::options(payload.len()).deserialize_from::<_, v1::DirRoot>(payload) {
+        Ok(it) => Ok(it.into()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Read the tree of a version 2 payload, which has no links.
+pub fn decode_v2(payload: &mut &[u8]) -> bincode::Result<crate::DirRoot> {
+    match limits::options(payload.len()).deserialize_from::<_, v2::DirRoot>(payload) {
+        Ok(it) => Ok(it.into()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Write `root` as 0.1.x did, which fails for trees with links.
+#[cfg(test)]
+pub fn encode_v0(root: &crate::DirRoot) -> Result<Vec<u8>, String> {
+    fn down(root: &crate::DirRoot) -> Result<v0::DirRoot, String> {
+        let mut files = vec![];
+        for f in &root.files {
+            let no_links = || format!("{}: no links in v0", f.name);
+            files.push(v0::FileInfo {
+                name: f.name.clone(),
+                file_type: match f.file_type {
+                    crate::FileType::Bin => v0::FileType::Bin,
+                    crate::FileType::Text => v0::FileType::Text,
+                    crate::FileType::Link => return Err(no_links()),
+                },
+                content: match &f.content {
+                    crate::FileContent::Text(s) => v0::FileContent::Text(s.clone()),
+                    crate::FileContent::Bin(b) => v0::FileContent::Bin(b.clone()),
+                    crate::FileContent::Link(_) => return Err(no_links()),
+                },
+            });
+        }
+        Ok(v0::DirRoot {
+            name: root.name.clone(),
+            dirs: root.dirs.iter().map(down).collect::<Result<_, _>>()?,
+            files,
+        })
+    }
+    bincode::serialize(&down(root)?).map_err(|err| err.to_string())
+}
+
+impl From<v0::DirRoot> for crate::DirRoot {
+    fn from(old: v0::DirRoot) -> Self {
+        Self {
+            name: old.name,
+            dirs: old.dirs.into_iter().map(Into::into).collect(),
+            files: old.files.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<v0::FileInfo> for crate::FileInfo {
+    fn from(old: v0::FileInfo) -> Self {
+        Self {
+            name: old.name,
+            file_type: old.file_type.into(),
+            content: old.content.into(),
+            mode: None,
+        }
+    }
+}
+
+impl From<v0::FileType> for crate::FileType {
+    fn from(old: v0::FileType) -> Self {
+        match old {
+            v0::FileType::Bin => crate::FileType::Bin,
+            v0::FileType::Text => crate::FileType::Text,
+        }
+    }
+}
+
+impl From<v0::FileContent> for crate::FileContent {
+    fn from(old: v0::FileContent) -> Self {
+        match old {
+            v0::FileContent::Text(s) => crate::FileContent::Text(s),
+            v0::FileContent::Bin(b) => crate::FileContent::Bin(b),
+        }
+    }
+}
+
+impl From<v2::DirRoot> for crate::DirRoot {
+    fn from(old: v2::DirRoot) -> Self {
+        Self {
+            name: old.name,
+            dirs: old.dirs.into_iter().map(Into::into).collect(),
+            files: old.files.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<v2::FileInfo> for crate::FileInfo {
+    fn from(old: v2::FileInfo) -> Self {
+        Self {
+            name: old.name,
+            file_type: old.file_type.into(),
+            content: old.content.into(),
+            mode: old.mode,
+        }
+    }
+}
@@ -1,36 +1,138 @@
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeSet, HashSet},
     env::consts::OS,
     fs::{self, File},
     io::{Read, Write},
+    path::Path,
 };
 
+pub mod capture;
+mod error;
+pub mod format;
+mod legacy;
+pub mod limits;
+pub mod meta;
+pub mod name;
+pub mod template;
+
+pub use error::Error;
+
+use capture::{CaptureOptions, Links, Rules};
+use limits::Limits;
+use meta::ProfileMeta;
+use template::Vars;
+
 // =============================================================================
 
-#[derive(Serialize, Deserialize)]
+/// A profile file: the captured tree plus its metadata.
+#[derive(Clone)]
+pub struct Profile {
+    pub meta: ProfileMeta,
+    pub root: DirRoot,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DirRoot {
     pub name: String,
+    #[serde(deserialize_with = "limits::dirs")]
     pub dirs: Vec<DirRoot>,
+    #[serde(deserialize_with = "limits::files")]
     pub files: Vec<FileInfo>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct FileInfo {
     pub name: String,
     pub file_type: FileType,
     pub content: FileContent,
+    /// Unix permission bits. `None` when they were not captured, on Windows
+    /// or in profiles older than format version 2, and the file is then
+    /// written with the default ones.
+    pub mode: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum FileType {
     Bin,
     Text,
+    Link,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum FileContent {
     Text(String),
     Bin(Vec<u8>),
+    /// Target of a symbolic link, `/` separated.
+    Link(String),
+}
+
+impl Profile {
+    pub fn new(root: DirRoot) -> Self {
+        Self {
+            meta: ProfileMeta::default(),
+            root,
+        }
+    }
+
+    pub fn save_as(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let buf = format::encode(self)?;
+        let io = |err| Error::io(path, err);
+        File::create(path).map_err(io)?.write_all(&buf).map_err(io)
+    }
+
+    /// Read a profile file, accepting both the current container format and
+    /// the headerless layout written by 0.1.x.
+    pub fn read_from(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::read_with_limits(path, &Limits::default())
+    }
+
+    /// Like [`Profile::read_from`], refusing profiles beyond `limits`.
+    pub fn read_with_limits(path: impl AsRef<Path>, limits: &Limits) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let io = |err| Error::io(path, err);
+        let f = File::open(path).map_err(io)?;
+        if f.metadata().map_err(io)?.len() > limits.max_size {
+            return Err(Error::TooLarge {
+                what: "bytes",
+                max: limits.max_size,
+            });
+        }
+        let mut buf: Vec<u8> = vec![];
+        f.take(limits.max_size + 1)
+            .read_to_end(&mut buf)
+            .map_err(io)?;
+        format::decode_with_limits(&buf, limits)
+    }
+
+    pub fn info(&self) {
+        if let Some(version) = &self.meta.version {
+            println!("Profile version: {}", version);
+        }
+        if !self.meta.params.is_empty() {
+            println!("This profile takes parameters: ");
+            for p in &self.meta.params {
+                match &p.help {
+                    Some(help) => println!("\t{}: {}", p.name, help),
+                    None => println!("\t{}", p.name),
+                }
+            }
+        }
+        if !self.meta.hooks.is_empty() {
+            println!("This profile runs hooks: ");
+            for h in &self.meta.hooks {
+                println!("\t{}: {}", h.stage.name(), h.describe());
+            }
+        }
+        if !self.meta.bootstrap.is_empty() {
+            println!("Removed again once the hooks ran: ");
+            for path in &self.meta.bootstrap {
+                println!("\t{}", path);
+            }
+        }
+        self.root.info();
+    }
 }
 
 impl DirRoot {
@@ -43,69 +145,167 @@ impl DirRoot {
     }
 
     pub fn from_dir(path: String) -> Result<Self, std::io::Error> {
-        get_dirs(String::new(), path, false)
+        Self::capture(path, &CaptureOptions::default())
     }
 
-    pub fn from_dir_raw(path: String) -> Result<Self, std::io::Error>  {
-        get_dirs(String::new(), path, true)
+    pub fn from_dir_raw(path: String) -> Result<Self, std::io::Error> {
+        let opts = CaptureOptions {
+            keep_empty_files: true,
+            ..Default::default()
+        };
+        Self::capture(path, &opts)
     }
 
-    pub fn save_as(self: &Self, path: &str) -> Result<(), String> {
-        let mut f = match File::create(path) {
-            Ok(it) => it,
-            Err(err) => return Err(err.to_string()),
-        };
+    pub fn capture(path: String, opts: &CaptureOptions) -> Result<Self, std::io::Error> {
+        let mut rules = Rules::new(Path::new(&path), opts)?;
+        get_dirs(String::new(), path, opts, &mut rules)
+    }
 
-        let buf = match bincode::serialize(&self) {
-            Ok(it) => it,
-            Err(err) => return Err(err.to_string()),
-        };
+    /// Copy of this tree with placeholders in names and text contents
+    /// substituted from `vars`. Binary contents are never touched.
+    pub fn render(&self, vars: &Vars) -> Self {
+        Self {
+            name: template::render(&self.name, vars),
+            dirs: self.dirs.iter().map(|d| d.render(vars)).collect(),
+            files: self.files.iter().map(|f| f.render(vars)).collect(),
+        }
+    }
 
-        match f.write_all(&buf) {
-            Ok(it) => it,
-            Err(err) => return Err(err.to_string()),
+    /// Replace occurrences of `value` and its case variants in names and text
+    /// contents with placeholders for `var`. Returns how many were replaced.
+    pub fn templatize(&mut self, value: &str, var: &str) -> usize {
+        let mut count = 0;
+        let mut replace = |s: &mut String| {
+            let (t, n) = template::templatize(s, value, var);
+            *s = t;
+            count += n;
         };
-        return Ok(());
+
+        fn walk(root: &mut DirRoot, replace: &mut dyn FnMut(&mut String)) {
+            for f in &mut root.files {
+                replace(&mut f.name);
+                if let FileContent::Text(s) | FileContent::Link(s) = &mut f.content {
+                    replace(s);
+                }
+            }
+            for d in &mut root.dirs {
+                replace(&mut d.name);
+                walk(d, replace);
+            }
+        }
+
+        walk(self, &mut replace);
+        count
     }
 
-    pub fn read_from(path: &str) -> Result<Self, String> {
-        let mut buf: Vec<u8> = vec![];
-        let mut f = match File::open(path) {
-            Ok(it) => it,
-            Err(err) => return Err(err.to_string()),
-        };
-        let _ = match f.read_to_end(&mut buf) {
-            Ok(it) => it,
-            Err(err) => return Err(err.to_string()),
-        };
-        Ok(match bincode::deserialize(&buf) {
-            Ok(it) => it,
-            Err(err) => return Err(err.to_string()),
-        })
+    /// Make sure every name in the tree is a plain file or dir name, used
+    /// once in its dir, so building it can't write outside of the target dir
+    /// or twice to the same path. Link targets must be something a link can
+    /// point to, where they point is up to [`DirRoot::check_links`].
+    pub fn check_names(&self) -> Result<(), Error> {
+        fn walk(root: &DirRoot, prefix: &str) -> Result<(), Error> {
+            let mut seen = HashSet::new();
+            let names = root.dirs.iter().map(|d| (&d.name, None));
+            let files = root.files.iter().map(|f| match &f.content {
+                FileContent::Link(target) => (&f.name, Some(target)),
+                _ => (&f.name, None),
+            });
+            for (n, target) in names.chain(files) {
+                let result = match prefix.is_empty() && name::RESERVED_TOP.contains(&n.as_str()) {
+                    true => Err(name::NameError::Reserved),
+                    false => name::check(n),
+                }
+                .and_then(|_| match seen.insert(n) {
+                    true => Ok(()),
+                    false => Err(name::NameError::Duplicate),
+                })
+                .and_then(|_| match target {
+                    Some(t) if t.is_empty() || t.contains('\0') => Err(name::NameError::LinkTarget),
+                    _ => Ok(()),
+                });
+                if let Err(error) = result {
+                    let path = format!("{}{}", prefix, n);
+                    return Err(Error::Name { path, error });
+                }
+            }
+            for d in &root.dirs {
+                walk(d, &format!("{}{}/", prefix, d.name))?;
+            }
+            Ok(())
+        }
+
+        walk(self, "")
+    }
+
+    /// Make sure every link in the tree points inside of it, as captured
+    /// without `allow_external_links`.
+    pub fn check_links(&self) -> Result<(), Error> {
+        fn walk(root: &DirRoot, prefix: &str) -> Result<(), Error> {
+            for f in &root.files {
+                if let FileContent::Link(target) = &f.content {
+                    if capture::escapes(Path::new(prefix), Path::new(target)) {
+                        return Err(Error::Link {
+                            path: format!("{}{}", prefix, f.name),
+                            target: target.clone(),
+                        });
+                    }
+                }
+            }
+            for d in &root.dirs {
+                walk(d, &format!("{}{}/", prefix, d.name))?;
+            }
+            Ok(())
+        }
+
+        walk(self, "")
+    }
+
+    /// Names of all placeholders used anywhere in this tree.
+    pub fn placeholders(&self) -> BTreeSet<String> {
+        fn collect(root: &DirRoot, found: &mut BTreeSet<String>) {
+            template::placeholders(&root.name, found);
+            for f in &root.files {
+                template::placeholders(&f.name, found);
+                if let FileContent::Text(s) | FileContent::Link(s) = &f.content {
+                    template::placeholders(s, found);
+                }
+            }
+            for d in &root.dirs {
+                collect(d, found);
+            }
+        }
+
+        let mut found = BTreeSet::new();
+        collect(self, &mut found);
+        found
     }
 
-    pub fn info(self: &Self) {
+    pub fn info(&self) {
         println!("This profile will create dirs: ");
         fn print_dirs(root: &DirRoot, prefix: String) {
             for i in &root.dirs {
                 println!("\t{}", prefix.clone() + &get_os_dir_sep() + &i.name);
-                print_dirs(&i, prefix.clone() + &get_os_dir_sep() + &i.name);
+                print_dirs(i, prefix.clone() + &get_os_dir_sep() + &i.name);
             }
         }
 
-        print_dirs(&self, ".".to_string());
+        print_dirs(self, ".".to_string());
 
         println!("And will write files: ");
         fn print_files(root: &DirRoot, prefix: String) {
             for i in &root.files {
-                println!("\t{}", prefix.clone() + &get_os_dir_sep() + &i.name);
+                let path = prefix.clone() + &get_os_dir_sep() + &i.name;
+                match &i.content {
+                    FileContent::Link(target) => println!("\t{} -> {}", path, target),
+                    _ => println!("\t{}", path),
+                }
             }
             for i in &root.dirs {
-                print_files(&i, prefix.clone() + &get_os_dir_sep() + &i.name);
+                print_files(i, prefix.clone() + &get_os_dir_sep() + &i.name);
             }
         }
 
-        print_files(&self, ".".to_string());
+        print_files(self, ".".to_string());
     }
 
     // pub fn get_info(self: &Self) -> String {
@@ -154,30 +354,69 @@ impl FileInfo {
             name: String::from(name),
             file_type,
             content,
+            mode: None,
+        }
+    }
+
+    pub fn render(&self, vars: &Vars) -> Self {
+        Self {
+            name: template::render(&self.name, vars),
+            file_type: self.file_type.clone(),
+            content: match &self.content {
+                FileContent::Text(s) => FileContent::Text(template::render(s, vars)),
+                FileContent::Bin(b) => FileContent::Bin(b.clone()),
+                FileContent::Link(t) => FileContent::Link(template::render(t, vars)),
+            },
+            mode: self.mode,
+        }
+    }
+
+    /// The content, or the target of a link.
+    pub fn as_bytes(&self) -> &[u8] {
+        match &self.content {
+            FileContent::Text(s) => s.as_bytes(),
+            FileContent::Bin(b) => b,
+            FileContent::Link(t) => t.as_bytes(),
         }
     }
 
-    pub fn write(self: &Self, path: &str) -> Result<usize, std::io::Error> {
+    pub fn is_link(&self) -> bool {
+        matches!(self.content, FileContent::Link(_))
+    }
+
+    pub fn write(&self, path: &str) -> Result<usize, std::io::Error> {
+        if let FileContent::Link(target) = &self.content {
+            make_link(target, Path::new(path))?;
+            return Ok(0);
+        }
         let mut f = File::create(path)?;
-        let content = match &self.content {
-            FileContent::Text(s) => s.as_bytes().to_vec(),
-            FileContent::Bin(b) => b.to_vec(),
-        };
-        f.write(&content)
+        let n = f.write(self.as_bytes())?;
+        if let Some(mode) = self.mode {
+            set_mode(Path::new(path), mode)?;
+        }
+        Ok(n)
     }
 }
 
 // =============================================================================
 // =============================================================================
 
-fn get_dirs(name: String, prefix: String, raw: bool) -> Result<DirRoot, std::io::Error> {
+fn get_dirs(
+    name: String,
+    prefix: String,
+    opts: &CaptureOptions,
+    rules: &mut Rules,
+) -> Result<DirRoot, std::io::Error> {
     let mut ret = DirRoot {
         name: name.clone(),
         dirs: vec![],
         files: vec![],
     };
 
-    for entry in fs::read_dir(prefix.clone() + &name)? {
+    let dir = prefix.clone() + &name;
+    rules.enter(Path::new(&dir))?;
+
+    for entry in fs::read_dir(&dir)? {
         let (f_name, f_type, f_path) = {
             let entry = entry.unwrap();
             (
@@ -187,31 +426,74 @@ fn get_dirs(name: String, prefix: String, raw: bool) -> Result<DirRoot, std::io:
             )
         };
 
-        if f_type.is_dir() {
+        // Where a followed link leads, otherwise the entry itself.
+        let (is_dir, is_file) = match (f_type.is_symlink(), opts.links) {
+            (true, Links::Skip) => continue,
+            (true, Links::Store) => (false, false),
+            (true, Links::Follow) => match fs::metadata(&f_path) {
+                Ok(meta) => (meta.is_dir(), meta.is_file()),
+                Err(err) => {
+                    return Err(std::io::Error::new(
+                        err.kind(),
+                        format!("{}: {}", f_path, err),
+                    ))
+                }
+            },
+            (false, _) => (f_type.is_dir(), f_type.is_file()),
+        };
+
+        if rules.is_ignored(Path::new(&f_path), is_dir) {
+            continue;
+        }
+
+        if f_type.is_symlink() {
+            let target = fs::read_link(&f_path)?;
+            rules.check_link(Path::new(&f_path), &target, opts)?;
+            if opts.links == Links::Store {
+                ret.files.push(FileInfo::new(
+                    &f_name,
+                    FileType::Link,
+                    FileContent::Link(link_target(&target)),
+                ));
+                continue;
+            }
+        }
+
+        if is_dir {
             // TODO: May cause unfriendly operation.
-            let next_root = get_dirs(f_name, prefix.clone() + &name + &get_os_dir_sep(), raw)?;
+            let next_root = get_dirs(
+                f_name,
+                prefix.clone() + &name + &get_os_dir_sep(),
+                opts,
+                rules,
+            )?;
 
-            if next_root.dirs.is_empty() && next_root.files.is_empty() && !raw {
+            if next_root.dirs.is_empty() && next_root.files.is_empty() && !opts.keep_empty_dirs {
                 continue;
             }
             ret.dirs.push(next_root);
-        } else if f_type.is_file() {
+        } else if is_file {
             let mut buf: Vec<u8> = vec![];
-            let _ = File::open(f_path)?.read_to_end(&mut buf);
+            let _ = File::open(&f_path)?.read_to_end(&mut buf);
 
-            if buf.is_empty() && !raw {
+            if buf.is_empty()
+                && !opts.keep_empty_files
+                && !capture::KEEP_MARKERS.contains(&f_name.as_str())
+            {
                 continue;
             }
 
-            let f_info = match String::from_utf8(buf.clone()) {
+            let mut f_info = match String::from_utf8(buf.clone()) {
                 Ok(content) => FileInfo::new(&f_name, FileType::Text, FileContent::Text(content)),
                 Err(_) => FileInfo::new(&f_name, FileType::Bin, FileContent::Bin(buf)),
             };
+            f_info.mode = mode_of(&fs::metadata(&f_path)?);
 
             ret.files.push(f_info);
         }
     }
 
+    rules.leave();
     Ok(ret)
 }
 
@@ -222,3 +504,66 @@ pub fn get_os_dir_sep() -> String {
     }
     .to_string()
 }
+
+/// Permission bits kept in profiles. Set-id and sticky bits are left out,
+/// a profile should not be able to hand those out.
+pub const MODE_MASK: u32 = 0o777;
+
+/// Permission bits of a file, `None` where there are no Unix permissions.
+#[cfg(unix)]
+pub fn mode_of(meta: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode() & MODE_MASK)
+}
+
+#[cfg(not(unix))]
+pub fn mode_of(_meta: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Set the permission bits of a file. Does nothing off Unix.
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> Result<(), std::io::Error> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & MODE_MASK))
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(_path: &Path, _mode: u32) -> Result<(), std::io::Error> {
+    Ok(())
+}
+
+/// What a profile captures at `path` by default: the target of a link, or
+/// the contents of a file.
+pub fn read_entry(path: &Path) -> Result<Vec<u8>, std::io::Error> {
+    match fs::symlink_metadata(path)?.file_type().is_symlink() {
+        true => Ok(link_target(&fs::read_link(path)?).into_bytes()),
+        false => fs::read(path),
+    }
+}
+
+fn link_target(target: &Path) -> String {
+    let target = target.to_string_lossy();
+    match OS {
+        "windows" => target.replace('\\', "/"),
+        _ => target.to_string(),
+    }
+}
+
+#[cfg(unix)]
+fn make_link(target: &str, path: &Path) -> Result<(), std::io::Error> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn make_link(target: &str, path: &Path) -> Result<(), std::io::Error> {
+    std::os::windows::fs::symlink_file(target.replace('/', "\\"), path)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn make_link(_target: &str, path: &Path) -> Result<(), std::io::Error> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("{}: symbolic links are not supported here", path.display()),
+    ))
+}
@@ -0,0 +1,180 @@
+//! Bounds on what decoding a profile may take.
+//!
+//! Profiles are shared, so a truncated, corrupt or crafted one has to fail
+//! with an error rather than exhaust memory or the stack. The size of the
+//! file is checked before it is read, and bincode is told how many bytes are
+//! left, so a length prefix claiming more is refused before anything is
+//! allocated for it. Entries and nesting are counted while the tree is
+//! deserialized, through `deserialize_with` on the `dirs` and `files` fields
+//! of every tree layout.
+
+use std::{cell::RefCell, fmt, marker::PhantomData};
+
+use bincode::Options;
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+
+use crate::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Size of the profile file, in bytes.
+    pub max_size: u64,
+    /// Files and dirs in the tree, together.
+    pub max_entries: u64,
+    /// How many levels of dirs may nest.
+    pub max_depth: u32,
+    /// Accept links pointing outside of the dir the profile is built into.
+    pub allow_external_links: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_size: 256 << 20,
+            max_entries: 100_000,
+            max_depth: 128,
+            allow_external_links: false,
+        }
+    }
+}
+
+/// What is left of the limits while a tree is being decoded.
+struct Budget {
+    limits: Limits,
+    depth: u32,
+    entries: u64,
+    /// Set once a limit is hit, so the error can say which.
+    exceeded: Option<Error>,
+}
+
+thread_local! {
+    static BUDGET: RefCell<Option<Budget>> = const { RefCell::new(None) };
+}
+
+/// Run `decode` with `limits` counted against the trees it deserializes.
+pub(crate) fn bounded<T>(
+    limits: &Limits,
+    decode: impl FnOnce() -> Result<T, Error>,
+) -> Result<T, Error> {
+    BUDGET.with(|b| {
+        *b.borrow_mut() = Some(Budget {
+            limits: *limits,
+            depth: 0,
+            entries: 0,
+            exceeded: None,
+        })
+    });
+    let result = decode();
+    let budget = BUDGET.with(|b| b.borrow_mut().take());
+    match (result, budget.and_then(|b| b.exceeded)) {
+        (Ok(it), _) => Ok(it),
+        (Err(_), Some(exceeded)) => Err(exceeded),
+        (Err(err), None) => Err(err),
+    }
+}
+
+/// bincode's default layout, refusing to read more than the `left` bytes.
+pub(crate) fn options(left: usize) -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(left as u64)
+}
+
+/// Update the budget, if one is set. Fails once a limit is passed.
+fn spend(f: impl FnOnce(&mut Budget) -> Option<Error>) -> Result<(), String> {
+    BUDGET.with(|b| match b.borrow_mut().as_mut() {
+        Some(budget) => match f(budget) {
+            Some(err) => {
+                let msg = err.to_string();
+                budget.exceeded = Some(err);
+                Err(msg)
+            }
+            None => Ok(()),
+        },
+        None => Ok(()),
+    })
+}
+
+fn enter() -> Result<(), String> {
+    spend(|b| {
+        b.depth += 1;
+        (b.depth > b.limits.max_depth).then_some(Error::TooLarge {
+            what: "levels of dirs",
+            max: b.limits.max_depth as u64,
+        })
+    })
+}
+
+fn leave() {
+    let _ = spend(|b| {
+        b.depth -= 1;
+        None
+    });
+}
+
+fn count() -> Result<(), String> {
+    spend(|b| {
+        b.entries += 1;
+        (b.entries > b.limits.max_entries).then_some(Error::TooLarge {
+            what: "files and dirs",
+            max: b.limits.max_entries,
+        })
+    })
+}
+
+/// Sequence of entries, each counted as it is read.
+struct Entries<T> {
+    /// The entries are dirs, one level below the ones before.
+    nested: bool,
+    marker: PhantomData<T>,
+}
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for Entries<T> {
+    type Value = Vec<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence of entries")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let nested = self.nested && seq.size_hint() != Some(0);
+        if nested {
+            enter().map_err(de::Error::custom)?;
+        }
+        let mut items = vec![];
+        while let Some(item) = seq.next_element()? {
+            count().map_err(de::Error::custom)?;
+            items.push(item);
+        }
+        if nested {
+            leave();
+        }
+        Ok(items)
+    }
+}
+
+pub(crate) fn dirs<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    d.deserialize_seq(Entries {
+        nested: true,
+        marker: PhantomData,
+    })
+}
+
+pub(crate) fn files<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    d.deserialize_seq(Entries {
+        nested: false,
+        marker: PhantomData,
+    })
+}
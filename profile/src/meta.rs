@@ -0,0 +1,453 @@
+//! Profile metadata, written by hand as `wsinit.toml` in the captured dir.
+//!
+//! ```toml
+//! version = "1.2.0"
+//! bootstrap = ["setup.sh"]
+//!
+//! [[params]]
+//! name = "license"
+//! type = "choice"
+//! choices = ["MIT", "Apache-2.0"]
+//! default = "MIT"
+//! help = "License of the new project"
+//!
+//! [[hooks]]
+//! stage = "post-build"
+//! script = "setup.sh"
+//! timeout = 300
+//! on_failure = "ignore"
+//! ```
+//!
+//! It is stored inside the profile as TOML text, so new keys can be added
+//! later without changing the binary layout of the profile file.
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::name;
+
+/// Name of the metadata file picked up by mkwsconfig.
+pub const META_FILE_NAME: &str = "wsinit.toml";
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ProfileMeta {
+    /// Free form version of the profile, recorded in workspace manifests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<Param>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
+    /// Files only there for the hooks, removed once they have run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bootstrap: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Param {
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub kind: ParamKind,
+    #[serde(
+        default,
+        deserialize_with = "scalar_to_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub default: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub choices: Vec<String>,
+    /// Regex the whole value has to match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub help: Option<String>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamKind {
+    #[default]
+    String,
+    Bool,
+    Int,
+    Choice,
+}
+
+/// A command wsinit runs at some stage of a build, in the workspace.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hook {
+    pub stage: Stage,
+    /// File of the profile to run, relative to `dir`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+    /// Command line to run instead of a script.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+    /// `shebang` to run the script itself, or the program to run it with,
+    /// like `bash` or `python3`. (default: sh)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interpreter: Option<String>,
+    /// Passed to the script.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Seconds before the hook is killed and counts as failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub on_failure: OnFailure,
+    /// For `post-file`, gitignore style glob of the files to run for,
+    /// relative to `dir`. (default: every file)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<String>,
+    /// Working dir, relative to the workspace. (default: the workspace)
+    #[serde(
+        default,
+        deserialize_with = "relative_dir",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub dir: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    /// Before anything is written.
+    PreBuild,
+    /// Once for every file written, with its path as last argument.
+    PostFile,
+    /// After every file is written.
+    PostBuild,
+}
+
+/// What a failing hook does to the build.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFailure {
+    /// Stop, keeping what was written so far.
+    #[default]
+    Abort,
+    /// Stop, and take back everything the build wrote.
+    Rollback,
+    /// Warn and go on.
+    Ignore,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::PreBuild => "pre-build",
+            Stage::PostFile => "post-file",
+            Stage::PostBuild => "post-build",
+        }
+    }
+}
+
+impl Hook {
+    /// Script or command line, for messages.
+    pub fn describe(&self) -> &str {
+        self.script
+            .as_deref()
+            .or(self.run.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Move the hook into subdir `dir` of the profile, for a dir captured
+    /// into a subdir of a bigger profile.
+    pub fn nest(&mut self, dir: &str) {
+        self.dir = Some(match &self.dir {
+            Some(inner) => format!("{}/{}", dir, inner),
+            None => dir.to_string(),
+        });
+    }
+
+    fn check(&self) -> Result<(), String> {
+        match (&self.script, &self.run) {
+            (Some(_), None) => {}
+            (None, Some(_)) => {
+                if self.interpreter.as_deref() == Some("shebang") {
+                    return Err("\"run\" can't use the shebang interpreter".to_string());
+                }
+                if !self.args.is_empty() {
+                    return Err("\"args\" are for scripts, put them in \"run\"".to_string());
+                }
+            }
+            _ => return Err("give either \"script\" or \"run\"".to_string()),
+        }
+        if self.files.is_some() && self.stage != Stage::PostFile {
+            return Err("\"files\" is only for post-file hooks".to_string());
+        }
+        if self.timeout == Some(0) {
+            return Err("timeout must be at least 1 second".to_string());
+        }
+        if let Some(dir) = &self.dir {
+            // The hook runs there, it must stay inside the workspace.
+            if let Err(err) = name::check_path(dir) {
+                return Err(format!("bad dir {:?}: {}", dir, err));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ProfileMeta {
+    pub fn from_toml(s: &str) -> Result<Self, String> {
+        let meta: Self = match toml::from_str(s) {
+            Ok(it) => it,
+            Err(err) => return Err(err.to_string()),
+        };
+        meta.check()?;
+        Ok(meta)
+    }
+
+    pub fn to_toml(&self) -> Result<String, String> {
+        match toml::to_string(self) {
+            Ok(it) => Ok(it),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    /// Add the declarations of `other`, used when several dirs are captured
+    /// into one profile.
+    pub fn merge(&mut self, other: ProfileMeta) -> Result<(), String> {
+        self.version = self.version.take().or(other.version);
+        self.params.extend(other.params);
+        self.hooks.extend(other.hooks);
+        self.bootstrap.extend(other.bootstrap);
+        self.check()
+    }
+
+    /// Move what refers to files into subdir `dir` of the profile, for a dir
+    /// captured into a subdir of a bigger profile.
+    pub fn nest(&mut self, dir: &str) {
+        self.hooks.iter_mut().for_each(|h| h.nest(dir));
+        for path in &mut self.bootstrap {
+            *path = format!("{}/{}", dir, path.trim_start_matches("./"));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.version.is_none()
+            && self.params.is_empty()
+            && self.hooks.is_empty()
+            && self.bootstrap.is_empty()
+    }
+
+    /// Catch mistakes in hand written metadata at capture time rather than
+    /// when somebody builds the profile.
+    fn check(&self) -> Result<(), String> {
+        for (i, p) in self.params.iter().enumerate() {
+            if self.params[..i].iter().any(|q| q.name == p.name) {
+                return Err(format!("param \"{}\" is declared twice", p.name));
+            }
+            if p.kind == ParamKind::Choice && p.choices.is_empty() {
+                return Err(format!("param \"{}\" is a choice without choices", p.name));
+            }
+            if let Some(pattern) = &p.pattern {
+                if let Err(err) = Regex::new(pattern) {
+                    return Err(format!("param \"{}\": bad pattern: {}", p.name, err));
+                }
+            }
+            if let Some(default) = &p.default {
+                if let Err(err) = p.validate(default) {
+                    return Err(format!("param \"{}\": bad default: {}", p.name, err));
+                }
+            }
+        }
+        for path in &self.bootstrap {
+            if path.starts_with('/') || path.split('/').any(|c| c == "..") {
+                return Err(format!(
+                    "bootstrap file \"{}\" must be relative and inside the profile",
+                    path
+                ));
+            }
+        }
+        for h in &self.hooks {
+            if let Err(err) = h.check() {
+                return Err(format!(
+                    "{} hook \"{}\": {}",
+                    h.stage.name(),
+                    h.describe(),
+                    err
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Param {
+    /// Check `value` against the declared type, choices and pattern.
+    /// Returns the value in canonical form (`true`/`false` for bools).
+    pub fn validate(&self, value: &str) -> Result<String, String> {
+        let value = match self.kind {
+            ParamKind::String | ParamKind::Choice => value.to_string(),
+            ParamKind::Bool => match value.to_lowercase().as_str() {
+                "true" | "yes" | "y" | "1" => "true".to_string(),
+                "false" | "no" | "n" | "0" => "false".to_string(),
+                _ => return Err(format!("\"{}\" is not a yes/no value", value)),
+            },
+            ParamKind::Int => match value.parse::<i64>() {
+                Ok(it) => it.to_string(),
+                Err(_) => return Err(format!("\"{}\" is not an integer", value)),
+            },
+        };
+
+        if !self.choices.is_empty() && !self.choices.contains(&value) {
+            return Err(format!(
+                "\"{}\" is not one of: {}",
+                value,
+                self.choices.join(", ")
+            ));
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let re = match Regex::new(&format!("^(?:{})$", pattern)) {
+                Ok(it) => it,
+                Err(err) => return Err(err.to_string()),
+            };
+            if !re.is_match(&value) {
+                return Err(format!("\"{}\" does not match /{}/", value, pattern));
+            }
+        }
+
+        Ok(value)
+    }
+}
+
+/// Let `default = true` and `default = 8080` be written without quotes.
+fn scalar_to_string<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    Ok(match Option::<toml::Value>::deserialize(d)? {
+        None => None,
+        Some(toml::Value::String(s)) => Some(s),
+        Some(toml::Value::Boolean(b)) => Some(b.to_string()),
+        Some(toml::Value::Integer(i)) => Some(i.to_string()),
+        Some(other) => {
+            return Err(serde::de::Error::custom(format!(
+                "expected a string, bool or integer, found {}",
+                other.type_str()
+            )))
+        }
+    })
+}
+
+/// Hook dir without a leading `./` or trailing `/`, or none for the
+/// workspace itself, so paths can be joined to it as they are.
+fn relative_dir<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    Ok(Option::<String>::deserialize(d)?.and_then(|dir| {
+        match dir.trim_start_matches("./").trim_end_matches('/') {
+            "" | "." => None,
+            dir => Some(dir.to_string()),
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_validates_params() {
+        let meta = ProfileMeta::from_toml(
+            r#"
+            [[params]]
+            name = "crate_name"
+            pattern = "[a-z_]+"
+
+            [[params]]
+            name = "ci"
+            type = "bool"
+            default = true
+
+            [[params]]
+            name = "license"
+            type = "choice"
+            choices = ["MIT", "Apache-2.0"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(meta.params[0].validate("demo_app").is_ok());
+        assert!(meta.params[0].validate("Demo").is_err());
+        assert_eq!(meta.params[1].default.as_deref(), Some("true"));
+        assert_eq!(meta.params[1].validate("no").unwrap(), "false");
+        assert!(meta.params[2].validate("GPL").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_declarations() {
+        assert!(ProfileMeta::from_toml("[[params]]\nname = \"a\"\ntype = \"choice\"").is_err());
+        assert!(
+            ProfileMeta::from_toml("[[params]]\nname = \"a\"\n[[params]]\nname = \"a\"\n").is_err()
+        );
+        assert!(ProfileMeta::from_toml(
+            "[[params]]\nname = \"a\"\ntype = \"int\"\ndefault = \"x\"\n"
+        )
+        .is_err());
+        // Top level keys written below a table end up in it.
+        assert!(
+            ProfileMeta::from_toml("[[params]]\nname = \"a\"\nbootstrap = [\"setup.sh\"]\n")
+                .is_err()
+        );
+        assert!(ProfileMeta::from_toml(
+            "[[hooks]]\nstage = \"pre-build\"\nrun = \"true\"\ntimout = 5\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_and_checks_hooks() {
+        let meta = ProfileMeta::from_toml(
+            r#"
+            [[hooks]]
+            stage = "post-build"
+            script = "setup.py"
+            interpreter = "python3"
+            on_failure = "rollback"
+
+            [[hooks]]
+            stage = "post-file"
+            run = "chmod +x"
+            files = "*.sh"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(meta.hooks[0].stage, Stage::PostBuild);
+        assert!(meta.hooks[0].on_failure == OnFailure::Rollback);
+        assert!(meta.hooks[1].on_failure == OnFailure::Abort);
+
+        assert!(ProfileMeta::from_toml(
+            "[[hooks]]
+stage = \"pre-build\"
+"
+        )
+        .is_err());
+        assert!(ProfileMeta::from_toml(
+            "[[hooks]]
+stage = \"pre-build\"
+run = \"true\"
+files = \"*\"
+"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn hooks_run_inside_the_workspace() {
+        let hook = |dir: &str| {
+            ProfileMeta::from_toml(&format!(
+                "[[hooks]]\nstage = \"post-build\"\nrun = \"true\"\ndir = {:?}\n",
+                dir
+            ))
+        };
+        let dir = |dir: &str| hook(dir).unwrap().hooks[0].dir.clone();
+        assert_eq!(dir("src").as_deref(), Some("src"));
+        assert_eq!(dir("./src/bin/").as_deref(), Some("src/bin"));
+        assert_eq!(dir("."), None);
+        assert_eq!(dir("./"), None);
+        for dir in ["/etc", "../elsewhere", "src/../..", "src//bin", ".wsinit"] {
+            assert!(hook(dir).is_err(), "{}", dir);
+        }
+    }
+}
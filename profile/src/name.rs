@@ -0,0 +1,127 @@
+//! What a file or dir name in a profile may be.
+//!
+//! Names are joined onto the target dir when a profile is built, so each one
+//! has to be a single plain name: a separator, `..` or an absolute path would
+//! let a profile write anywhere. Profiles are checked when they are loaded,
+//! and again after rendering, since template variables end up in names.
+
+use std::fmt;
+
+/// Top level names wsinit keeps for itself in a workspace.
+pub const RESERVED_TOP: [&str; 1] = [".wsinit"];
+
+/// Device names Windows reserves in every dir, with or without extension.
+const WINDOWS_DEVICES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameError {
+    Empty,
+    /// `.` or `..`.
+    Dots,
+    Nul,
+    Absolute,
+    Separator,
+    Reserved,
+    /// Another file or dir in the same dir has the name.
+    Duplicate,
+    /// A link whose target is empty or has a NUL byte.
+    LinkTarget,
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NameError::Empty => "the name is empty",
+            NameError::Dots => "\".\" and \"..\" are not names",
+            NameError::Nul => "it contains a NUL byte",
+            NameError::Absolute => "it is an absolute path",
+            NameError::Separator => "it contains a path separator",
+            NameError::Reserved => "the name is reserved",
+            NameError::Duplicate => "another entry in the dir has the same name",
+            NameError::LinkTarget => "the link target is empty or contains a NUL byte",
+        })
+    }
+}
+
+/// Check one name. Both `/` and `\` count as separators everywhere, as a
+/// profile may be built on another OS than it was captured on. Drive
+/// prefixes and device names only mean something on Windows, and are only
+/// refused there.
+pub fn check(name: &str) -> Result<(), NameError> {
+    if name.is_empty() {
+        return Err(NameError::Empty);
+    }
+    if name == "." || name == ".." {
+        return Err(NameError::Dots);
+    }
+    if name.contains('\0') {
+        return Err(NameError::Nul);
+    }
+    if name.starts_with(['/', '\\']) || (cfg!(windows) && has_drive(name)) {
+        return Err(NameError::Absolute);
+    }
+    if name.contains(['/', '\\']) {
+        return Err(NameError::Separator);
+    }
+    if cfg!(windows) && is_device(name) {
+        return Err(NameError::Reserved);
+    }
+    Ok(())
+}
+
+/// Check a `/` separated path relative to the target dir, one name at a
+/// time, as recorded in a workspace manifest.
+pub fn check_path(path: &str) -> Result<(), NameError> {
+    for (i, n) in path.split('/').enumerate() {
+        if i == 0 && RESERVED_TOP.contains(&n) {
+            return Err(NameError::Reserved);
+        }
+        check(n)?;
+    }
+    Ok(())
+}
+
+fn has_drive(name: &str) -> bool {
+    let b = name.as_bytes();
+    b.len() >= 2 && b[0].is_ascii_alphabetic() && b[1] == b':'
+}
+
+fn is_device(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    WINDOWS_DEVICES.iter().any(|d| d.eq_ignore_ascii_case(stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_names_that_leave_the_dir() {
+        assert_eq!(check("main.rs"), Ok(()));
+        assert_eq!(check(".envrc"), Ok(()));
+        assert_eq!(check("..."), Ok(()));
+        assert_eq!(check(""), Err(NameError::Empty));
+        assert_eq!(check(".."), Err(NameError::Dots));
+        assert_eq!(check("a\0b"), Err(NameError::Nul));
+        assert_eq!(check("/etc"), Err(NameError::Absolute));
+        assert_eq!(check("../../.bashrc"), Err(NameError::Separator));
+        assert_eq!(check("a\\b"), Err(NameError::Separator));
+    }
+
+    #[test]
+    fn refuses_paths_that_leave_the_dir() {
+        assert_eq!(check_path("src/main.rs"), Ok(()));
+        assert_eq!(check_path(""), Err(NameError::Empty));
+        assert_eq!(check_path("src//main.rs"), Err(NameError::Empty));
+        assert_eq!(check_path("/etc/passwd"), Err(NameError::Empty));
+        assert_eq!(
+            check_path("../../.ssh/authorized_keys"),
+            Err(NameError::Dots)
+        );
+        assert_eq!(check_path("a\\..\\b"), Err(NameError::Separator));
+        assert_eq!(check_path(".wsinit/manifest"), Err(NameError::Reserved));
+    }
+}
@@ -0,0 +1,265 @@
+//! `{{ name }}` placeholders in file contents and file names.
+//!
+//! Only identifiers (`[A-Za-z_][A-Za-z0-9_]*`) are treated as placeholders.
+//! Anything else between braces, such as `${{ github.ref }}` in a CI
+//! workflow, is copied through untouched, and so is a placeholder without a
+//! value, so a profile never loses text it did not mean to template.
+//!
+//! A placeholder can change the case of its value with a filter, e.g.
+//! `{{ project_name | snake_case }}`. See [`Filter`] for the list.
+
+use std::collections::{BTreeSet, HashMap};
+
+pub type Vars = HashMap<String, String>;
+
+const OPEN: &str = "{{";
+const CLOSE: &str = "}}";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// `my_app`
+    SnakeCase,
+    /// `my-app`
+    KebabCase,
+    /// `MyApp`
+    PascalCase,
+}
+
+impl Filter {
+    pub const ALL: [Filter; 3] = [Filter::SnakeCase, Filter::KebabCase, Filter::PascalCase];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Filter::SnakeCase => "snake_case",
+            Filter::KebabCase => "kebab_case",
+            Filter::PascalCase => "pascal_case",
+        }
+    }
+
+    pub fn apply(self, s: &str) -> String {
+        let words = split_words(s);
+        match self {
+            Filter::SnakeCase => words.join("_").to_lowercase(),
+            Filter::KebabCase => words.join("-").to_lowercase(),
+            Filter::PascalCase => words
+                .iter()
+                .map(|w| {
+                    let mut chars = w.chars();
+                    match chars.next() {
+                        Some(c) => c
+                            .to_uppercase()
+                            .chain(chars.flat_map(char::to_lowercase))
+                            .collect(),
+                        None => String::new(),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    fn from_name(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.name() == s)
+    }
+}
+
+struct Placeholder<'a> {
+    name: &'a str,
+    filter: Option<Filter>,
+}
+
+/// Substitute every placeholder in `s` that has a value in `vars`.
+pub fn render(s: &str, vars: &Vars) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some((before, p, after)) = next_placeholder(rest) {
+        ret.push_str(before);
+        match (vars.get(p.name), p.filter) {
+            (Some(value), Some(filter)) => ret.push_str(&filter.apply(value)),
+            (Some(value), None) => ret.push_str(value),
+            (None, _) => ret.push_str(&rest[before.len()..rest.len() - after.len()]),
+        }
+        rest = after;
+    }
+    ret.push_str(rest);
+    ret
+}
+
+/// Names of all placeholders used in `s`.
+pub fn placeholders(s: &str, found: &mut BTreeSet<String>) {
+    let mut rest = s;
+    while let Some((_, p, after)) = next_placeholder(rest) {
+        found.insert(p.name.to_string());
+        rest = after;
+    }
+}
+
+/// Replace whole-word occurrences of `value` and its case variants in `s`
+/// with placeholders for `var`. The reverse of [`render`], used to turn a
+/// finished project into a template. Returns the new text and the number of
+/// replacements.
+///
+/// An occurrence counts as a whole word when it is not preceded by a letter
+/// or digit and not followed by a lowercase letter or digit, so `my_app` is
+/// found in `my_app_test` and `MyApp` in `MyAppConfig`, but `app` is not
+/// found in `application`.
+pub fn templatize(s: &str, value: &str, var: &str) -> (String, usize) {
+    let mut variants: Vec<(String, String)> = vec![(value.to_string(), format!("{{{{{}}}}}", var))];
+    for filter in Filter::ALL {
+        let text = filter.apply(value);
+        if !variants.iter().any(|(t, _)| *t == text) {
+            variants.push((text, format!("{{{{{}|{}}}}}", var, filter.name())));
+        }
+    }
+    variants.retain(|(text, _)| !text.is_empty());
+    variants.sort_by_key(|(text, _)| std::cmp::Reverse(text.len()));
+
+    let mut ret = String::with_capacity(s.len());
+    let mut count = 0;
+    let mut i = 0;
+    'scan: while i < s.len() {
+        let prev_is_word = s[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric());
+        if !prev_is_word {
+            for (text, placeholder) in &variants {
+                if !s[i..].starts_with(text.as_str()) {
+                    continue;
+                }
+                let next = s[i + text.len()..].chars().next();
+                if next.is_some_and(|c| c.is_lowercase() || c.is_numeric()) {
+                    continue;
+                }
+                ret.push_str(placeholder);
+                count += 1;
+                i += text.len();
+                continue 'scan;
+            }
+        }
+        let c = s[i..].chars().next().unwrap();
+        ret.push(c);
+        i += c.len_utf8();
+    }
+    (ret, count)
+}
+
+/// Find the next placeholder in `s`, returning the text before it, the
+/// placeholder and the text after it.
+fn next_placeholder(s: &str) -> Option<(&str, Placeholder<'_>, &str)> {
+    let mut from = 0;
+    while let Some(start) = s[from..].find(OPEN).map(|i| i + from) {
+        let inner_start = start + OPEN.len();
+        let end = inner_start + s[inner_start..].find(CLOSE)?;
+        if let Some(p) = parse_placeholder(&s[inner_start..end]) {
+            return Some((&s[..start], p, &s[end + CLOSE.len()..]));
+        }
+        from = start + 1;
+    }
+    None
+}
+
+fn parse_placeholder(inner: &str) -> Option<Placeholder<'_>> {
+    let (name, filter) = match inner.split_once('|') {
+        Some((name, filter)) => (name.trim(), Some(Filter::from_name(filter.trim())?)),
+        None => (inner.trim(), None),
+    };
+    match is_ident(name) {
+        true => Some(Placeholder { name, filter }),
+        false => None,
+    }
+}
+
+/// Split `MyApp`, `my-app` or `my_app` into `["My", "App"]` / `["my", "app"]`.
+fn split_words(s: &str) -> Vec<String> {
+    let mut words: Vec<String> = vec![];
+    let mut word = String::new();
+    let mut prev_lower = false;
+    for c in s.chars() {
+        if !c.is_alphanumeric() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            prev_lower = false;
+            continue;
+        }
+        if c.is_uppercase() && prev_lower {
+            words.push(std::mem::take(&mut word));
+        }
+        prev_lower = c.is_lowercase() || c.is_numeric();
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> Vars {
+        Vars::from([("project_name".to_string(), "demo".to_string())])
+    }
+
+    #[test]
+    fn substitutes_known_names() {
+        assert_eq!(
+            render("name = \"{{project_name}}\"", &vars()),
+            "name = \"demo\""
+        );
+        assert_eq!(render("{{ project_name }}.rs", &vars()), "demo.rs");
+    }
+
+    #[test]
+    fn keeps_unknown_and_foreign_braces() {
+        let s = "ref: ${{ github.ref }} {{author}} {{project_name}}";
+        assert_eq!(render(s, &vars()), "ref: ${{ github.ref }} {{author}} demo");
+
+        let mut found = BTreeSet::new();
+        placeholders(s, &mut found);
+        assert_eq!(
+            found.into_iter().collect::<Vec<_>>(),
+            ["author", "project_name"]
+        );
+    }
+
+    #[test]
+    fn applies_case_filters() {
+        let vars = Vars::from([("project_name".to_string(), "my-app".to_string())]);
+        assert_eq!(
+            render(
+                "{{project_name|snake_case}} {{ project_name | pascal_case }}",
+                &vars
+            ),
+            "my_app MyApp"
+        );
+        assert_eq!(
+            render("{{project_name|shout}}", &vars),
+            "{{project_name|shout}}"
+        );
+    }
+
+    #[test]
+    fn templatize_is_the_reverse_of_render() {
+        let s = "name = \"my-app\"\nuse my_app::MyAppConfig;\n// application\n";
+        let (t, count) = templatize(s, "my-app", "project_name");
+        assert_eq!(count, 3);
+        assert_eq!(
+            t,
+            "name = \"{{project_name}}\"\nuse {{project_name|snake_case}}::{{project_name|pascal_case}}Config;\n// application\n"
+        );
+
+        let vars = Vars::from([("project_name".to_string(), "my-app".to_string())]);
+        assert_eq!(render(&t, &vars), s);
+    }
+}
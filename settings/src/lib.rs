@@ -1,26 +1,98 @@
 use std::{
-    fs::File,
-    io::{Read, Write},
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 
+use roots::{ProfileRoot, Roots};
 use serde::{Deserialize, Serialize};
 
+pub mod roots;
+
+#[derive(Debug)]
+pub enum Error {
+    /// No settings file yet.
+    NotFound(PathBuf),
+    PermissionDenied {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// The settings file is not valid.
+    Corrupt {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    Serialize(toml::ser::Error),
+    /// Settings that can be read but do not make sense.
+    Invalid(String),
+}
+
+impl Error {
+    fn io(path: &Path, source: io::Error) -> Self {
+        let path = path.to_path_buf();
+        match source.kind() {
+            io::ErrorKind::NotFound => Error::NotFound(path),
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied { path, source },
+            _ => Error::Io { path, source },
+        }
+    }
+
+    /// Process exit code for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::NotFound(_) => exit_code::NOT_FOUND,
+            Error::PermissionDenied { .. } => exit_code::PERMISSION_DENIED,
+            Error::Corrupt { .. } | Error::Invalid(_) => exit_code::INVALID,
+            Error::Io { .. } | Error::Serialize(_) => exit_code::FAILURE,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(path) => write!(f, "{}: not found", path.display()),
+            Error::PermissionDenied { path, .. } => {
+                write!(f, "{}: permission denied", path.display())
+            }
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Corrupt { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Serialize(err) => write!(f, "failed to write settings: {}", err),
+            Error::Invalid(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::NotFound(_) | Error::Invalid(_) => None,
+            Error::PermissionDenied { source, .. } | Error::Io { source, .. } => Some(source),
+            Error::Corrupt { source, .. } => Some(source),
+            Error::Serialize(err) => Some(err),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Settings {
     pub wsinit: Wsinit,
 }
 
 impl Settings {
-    pub fn read_from(path: &str) -> Result<Self, (usize, String)> {
-        let mut f = match File::open(path) {
-            Ok(it) => it,
-            Err(err) => return Err((1, err.to_string())),
-        };
-        let mut s = String::new();
-        let _ = f.read_to_string(&mut s);
-        Ok(match toml::from_str(&s) {
-            Ok(it) => it,
-            Err(err) => return Err((2, err.to_string())),
+    pub fn read_from(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+        toml::from_str(&s).map_err(|source| Error::Corrupt {
+            path: path.to_path_buf(),
+            source,
         })
     }
 
@@ -30,31 +102,201 @@ impl Settings {
         }
     }
 
-    pub fn write(self: &Self, path: &str) -> Result<(), std::io::Error> {
-        let mut f = File::open(path).unwrap_or(File::create(path)?);
-        let s = toml::to_string(self).unwrap();
-        write!(f, "{}", s)?;
-        Ok(())
+    /// Where profiles are looked up, the user store in `config_dir` first.
+    pub fn roots(&self, config_dir: &Path) -> Result<Roots, Error> {
+        Roots::new(config_dir, &self.wsinit.profile_roots)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let s = toml::to_string(self).map_err(Error::Serialize)?;
+        let io = |err| Error::io(path, err);
+        let mut f = File::create(path).map_err(io)?;
+        write!(f, "{}", s).map_err(io)
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Wsinit {
     default_config: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    on_conflict: Option<ConflictPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    manifest: Option<bool>,
+    /// Digest of the hooks the user approved, by profile name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    trusted_hooks: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Limits::is_empty")]
+    pub limits: Limits,
+    /// Shared dirs of profiles, looked up after the user store in this order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    profile_roots: Vec<ProfileRoot>,
+}
+
+/// Bounds on profiles wsinit reads, where they differ from the defaults.
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+pub struct Limits {
+    /// Size of a profile file, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    /// Files and dirs in a profile, together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_entries: Option<u64>,
+    /// How many levels of dirs may nest in a profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<u32>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.max_size.is_none() && self.max_entries.is_none() && self.max_depth.is_none()
+    }
 }
 
 impl Wsinit {
     pub fn new() -> Self {
         Wsinit {
             default_config: String::new(),
+            on_conflict: None,
+            manifest: None,
+            trusted_hooks: BTreeMap::new(),
+            limits: Limits::default(),
+            profile_roots: vec![],
         }
     }
 
-    pub fn get_default(self: &Self) -> String {
+    /// What to do with files the profile would overwrite, if configured.
+    pub fn get_on_conflict(&self) -> Option<ConflictPolicy> {
+        self.on_conflict
+    }
+
+    /// Whether builds are recorded in `.wsinit/manifest` of the workspace.
+    pub fn get_manifest(&self) -> bool {
+        self.manifest.unwrap_or(false)
+    }
+
+    /// Whether the user approved hooks with this digest for `profile`.
+    pub fn is_trusted(&self, profile: &str, digest: &str) -> bool {
+        self.trusted_hooks.get(profile).is_some_and(|d| d == digest)
+    }
+
+    pub fn trust(&mut self, profile: &str, digest: &str) {
+        self.trusted_hooks
+            .insert(profile.to_string(), digest.to_string());
+    }
+
+    pub fn get_default(&self) -> String {
         self.default_config.clone()
     }
 
-    pub fn set_default(self: &mut Self, default: &str) {
+    pub fn set_default(&mut self, default: &str) {
         self.default_config = default.to_string();
     }
 }
+
+impl Default for Wsinit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What to do when the profile has a file that already exists in the target.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Stop before writing anything.
+    Abort,
+    /// Keep the existing file.
+    Skip,
+    /// Replace the existing file.
+    Overwrite,
+    /// Move the existing file to `<name>.orig`, then write.
+    Backup,
+    /// Keep the existing file, write the new one as `<name>.new`.
+    Rename,
+    /// Show a diff and ask for each file.
+    Prompt,
+}
+
+impl ConflictPolicy {
+    pub const ALL: [ConflictPolicy; 6] = [
+        ConflictPolicy::Abort,
+        ConflictPolicy::Skip,
+        ConflictPolicy::Overwrite,
+        ConflictPolicy::Backup,
+        ConflictPolicy::Rename,
+        ConflictPolicy::Prompt,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ConflictPolicy::Abort => "abort",
+            ConflictPolicy::Skip => "skip",
+            ConflictPolicy::Overwrite => "overwrite",
+            ConflictPolicy::Backup => "backup",
+            ConflictPolicy::Rename => "rename",
+            ConflictPolicy::Prompt => "prompt",
+        }
+    }
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|p| p.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|p| p.name()).collect();
+                format!("expected one of: {}", names.join(", "))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_io_errors_into_variants() {
+        let path = Path::new("settings.toml");
+        let error = |kind| Error::io(path, io::Error::from(kind));
+
+        let err = error(io::ErrorKind::NotFound);
+        assert!(matches!(&err, Error::NotFound(p) if p == path));
+        assert_eq!(err.exit_code(), exit_code::NOT_FOUND);
+        let err = error(io::ErrorKind::PermissionDenied);
+        assert!(matches!(err, Error::PermissionDenied { .. }));
+        assert_eq!(err.exit_code(), exit_code::PERMISSION_DENIED);
+        let err = error(io::ErrorKind::Other);
+        assert!(matches!(err, Error::Io { .. }));
+        assert_eq!(err.exit_code(), exit_code::FAILURE);
+    }
+
+    #[test]
+    fn corrupt_settings_are_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.toml");
+        fs::write(&path, "[wsinit\n").unwrap();
+        let err = Settings::read_from(&path).err().unwrap();
+        assert!(matches!(err, Error::Corrupt { .. }));
+        assert_eq!(err.exit_code(), exit_code::INVALID);
+        assert_eq!(
+            Error::Invalid("bad root".to_string()).exit_code(),
+            exit_code::INVALID
+        );
+    }
+}
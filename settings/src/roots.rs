@@ -0,0 +1,195 @@
+//! Dirs profiles are looked up in.
+//!
+//! The user store, `profiles` in the config dir, always comes first, followed
+//! by the `[[wsinit.profile_roots]]` of the settings in the order they are
+//! listed. A profile shadows those of the same name in the roots after its
+//! own. Configured roots are read-only unless they say otherwise: wsinit never
+//! writes to a root, and mkwsconfig only saves into one that is not
+//! read-only. A root that does not exist, like an unmounted share, is passed
+//! over.
+
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use paths::get_profile_file_name;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// Name of the user store.
+pub const USER_ROOT: &str = "user";
+
+const PROFILES_DIR_NAME: &str = "profiles";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProfileRoot {
+    /// Shown by `wsinit --list` and given to `mkwsconfig --root`.
+    pub name: String,
+    /// Dir the profile files are in. A relative path is relative to the
+    /// config dir.
+    pub path: PathBuf,
+    #[serde(default = "read_only_default")]
+    pub read_only: bool,
+}
+
+fn read_only_default() -> bool {
+    true
+}
+
+/// A profile found in one of the roots.
+#[derive(Debug)]
+pub struct Listed<'a> {
+    pub root: &'a ProfileRoot,
+    /// File name of the profile.
+    pub name: String,
+    /// Name of the earlier root with a profile of the same name.
+    pub shadowed_by: Option<&'a str>,
+}
+
+/// The roots to look profiles up in, in order.
+#[derive(Debug)]
+pub struct Roots(Vec<ProfileRoot>);
+
+impl Roots {
+    /// The user store in `config_dir`, then `configured`. Fails if two roots
+    /// have the same name.
+    pub fn new(config_dir: &Path, configured: &[ProfileRoot]) -> Result<Self, Error> {
+        let mut roots = vec![ProfileRoot {
+            name: USER_ROOT.to_string(),
+            path: config_dir.join(PROFILES_DIR_NAME),
+            read_only: false,
+        }];
+        for root in configured {
+            if roots.iter().any(|r| r.name == root.name) {
+                return Err(Error::Invalid(format!(
+                    "two profile roots are named \"{}\"",
+                    root.name
+                )));
+            }
+            roots.push(ProfileRoot {
+                path: config_dir.join(&root.path),
+                ..root.clone()
+            });
+        }
+        Ok(Self(roots))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ProfileRoot> {
+        self.0.iter()
+    }
+
+    /// The user store.
+    pub fn user(&self) -> &ProfileRoot {
+        &self.0[0]
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ProfileRoot> {
+        self.0.iter().find(|r| r.name == name)
+    }
+
+    /// Path of the profile called `profile` in the first root that has it.
+    pub fn find(&self, profile: &str) -> Option<(&ProfileRoot, PathBuf)> {
+        let file_name = get_profile_file_name(profile);
+        self.0
+            .iter()
+            .map(|r| (r, r.path.join(&file_name)))
+            .find(|(_, path)| path.is_file())
+    }
+
+    /// Every profile of every root, in root order, with the roots that could
+    /// not be read. Roots that do not exist are left out of both.
+    pub fn list(&self) -> (Vec<Listed<'_>>, Vec<(&ProfileRoot, io::Error)>) {
+        let mut listed: Vec<Listed> = vec![];
+        let mut failed = vec![];
+        for root in &self.0 {
+            let names = match read_names(&root.path) {
+                Ok(it) => it,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    failed.push((root, err));
+                    continue;
+                }
+            };
+            for name in names {
+                let shadowed_by = listed
+                    .iter()
+                    .find(|l| l.name == name && l.shadowed_by.is_none())
+                    .map(|l| l.root.name.as_str());
+                listed.push(Listed {
+                    root,
+                    name,
+                    shadowed_by,
+                });
+            }
+        }
+        (listed, failed)
+    }
+}
+
+fn read_names(dir: &Path) -> Result<BTreeSet<String>, io::Error> {
+    let mut names = BTreeSet::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            names.insert(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn earlier_roots_shadow_later_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        for (root, profile) in [("profiles", "a"), ("team", "a"), ("team", "b")] {
+            fs::create_dir_all(dir.join(root)).unwrap();
+            fs::write(dir.join(root).join(get_profile_file_name(profile)), "").unwrap();
+        }
+        let configured = [
+            ProfileRoot {
+                name: "team".to_string(),
+                path: "team".into(),
+                read_only: true,
+            },
+            ProfileRoot {
+                name: "system".to_string(),
+                path: dir.join("missing"),
+                read_only: true,
+            },
+        ];
+        let roots = Roots::new(dir, &configured).unwrap();
+
+        assert_eq!(roots.find("a").unwrap().0.name, USER_ROOT);
+        assert_eq!(roots.find("b").unwrap().0.name, "team");
+        assert!(roots.find("c").is_none());
+
+        let (listed, failed) = roots.list();
+        let listed: Vec<_> = listed
+            .iter()
+            .map(|l| (l.root.name.as_str(), l.name.as_str(), l.shadowed_by))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                (USER_ROOT, "a.bincode", None),
+                ("team", "a.bincode", Some(USER_ROOT)),
+                ("team", "b.bincode", None),
+            ]
+        );
+        assert!(failed.is_empty());
+
+        let mut twice = configured.to_vec();
+        twice[1].name = USER_ROOT.to_string();
+        assert!(Roots::new(dir, &twice).is_err());
+    }
+}
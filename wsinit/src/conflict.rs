@@ -0,0 +1,150 @@
+//! Decide what happens to files the profile would overwrite.
+
+use std::{
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use settings::ConflictPolicy;
+use similar::TextDiff;
+
+use crate::plan::{Action, Entry, State, Step};
+
+const BACKUP_SUFFIX: &str = ".orig";
+const RENAME_SUFFIX: &str = ".new";
+
+/// Set the action of every step that would overwrite a file, following
+/// `policy`. With [`ConflictPolicy::Prompt`] the user is asked for each
+/// file, before anything is written.
+pub fn resolve(steps: &mut [Step], target: &Path, policy: ConflictPolicy) {
+    for step in steps.iter_mut().filter(|s| s.state == State::Overwrite) {
+        let policy = match policy {
+            ConflictPolicy::Prompt => ask(step, target),
+            policy => policy,
+        };
+        step.action = match policy {
+            ConflictPolicy::Overwrite => Action::Write,
+            ConflictPolicy::Abort | ConflictPolicy::Skip => Action::Keep,
+            ConflictPolicy::Backup => Action::Backup(free_name(target, &step.path, BACKUP_SUFFIX)),
+            ConflictPolicy::Rename => Action::WriteAs(free_name(target, &step.path, RENAME_SUFFIX)),
+            ConflictPolicy::Prompt => unreachable!(),
+        };
+    }
+}
+
+fn ask(step: &Step, target: &Path) -> ConflictPolicy {
+    if !io::stdin().is_terminal() {
+        eprintln!("E: --on-conflict prompt needs a terminal, choose another policy.");
+        exit(exit_code::USAGE);
+    }
+
+    let new = match step.entry {
+        Entry::File(f) => f.as_bytes(),
+        Entry::Dir => return ConflictPolicy::Skip,
+    };
+    let old = fs::read(target.join(&step.path)).unwrap_or_default();
+    print_diff(&step.path, &old, new);
+
+    loop {
+        print!(
+            "{} exists. [o]verwrite, [s]kip, [b]ackup, [r]ename new, [q]uit: ",
+            step.path.display()
+        );
+        let _ = io::stdout().flush();
+
+        let buf = crate::read_answer();
+        match buf.trim().to_lowercase().as_str() {
+            "o" => return ConflictPolicy::Overwrite,
+            "s" => return ConflictPolicy::Skip,
+            "b" => return ConflictPolicy::Backup,
+            "r" => return ConflictPolicy::Rename,
+            "q" => {
+                println!("Give up!");
+                exit(exit_code::FAILURE);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn print_diff(path: &Path, old: &[u8], new: &[u8]) {
+    match (std::str::from_utf8(old), std::str::from_utf8(new)) {
+        (Ok(old), Ok(new)) => {
+            let name = path.display().to_string();
+            print!(
+                "{}",
+                TextDiff::from_lines(old, new)
+                    .unified_diff()
+                    .header(&format!("a/{}", name), &format!("b/{}", name))
+            );
+        }
+        _ => println!("Binary file {} differs.", path.display()),
+    }
+}
+
+/// `path` + `suffix`, or `path` + `suffix.N` for the first N not taken.
+fn free_name(target: &Path, path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    let base = PathBuf::from(name);
+
+    let mut candidate = base.clone();
+    let mut n = 1;
+    while fs::symlink_metadata(target.join(&candidate)).is_ok() {
+        let mut name = base.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        candidate = PathBuf::from(name);
+        n += 1;
+    }
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::plan;
+    use profile::{DirRoot, FileContent, FileInfo, FileType};
+
+    /// Resolve a profile with `a.txt` and `b.txt` against a target that has
+    /// both, one of them the same, and a leftover backup of the other.
+    fn resolved(policy: ConflictPolicy) -> Vec<(String, Action)> {
+        let target = tempfile::tempdir().unwrap();
+        fs::write(target.path().join("a.txt"), "old").unwrap();
+        fs::write(target.path().join("a.txt.orig"), "older").unwrap();
+        fs::write(target.path().join("b.txt"), "b").unwrap();
+
+        let mut root = DirRoot::new("");
+        for (name, content) in [("a.txt", "new"), ("b.txt", "b"), ("c.txt", "c")] {
+            root.files.push(FileInfo::new(
+                name,
+                FileType::Text,
+                FileContent::Text(content.to_string()),
+            ));
+        }
+        let mut steps = plan(&root, target.path());
+        resolve(&mut steps, target.path(), policy);
+        steps
+            .into_iter()
+            .map(|s| (s.path.display().to_string(), s.action))
+            .collect()
+    }
+
+    fn expect(a: Action) -> Vec<(String, Action)> {
+        vec![
+            ("a.txt".to_string(), a),
+            ("b.txt".to_string(), Action::Keep),
+            ("c.txt".to_string(), Action::Write),
+        ]
+    }
+
+    #[test]
+    fn follows_the_policy() {
+        assert!(resolved(ConflictPolicy::Overwrite) == expect(Action::Write));
+        assert!(resolved(ConflictPolicy::Abort) == expect(Action::Keep));
+        assert!(resolved(ConflictPolicy::Skip) == expect(Action::Keep));
+        assert!(resolved(ConflictPolicy::Backup) == expect(Action::Backup("a.txt.orig.1".into())));
+        assert!(resolved(ConflictPolicy::Rename) == expect(Action::WriteAs("a.txt.new".into())));
+    }
+}
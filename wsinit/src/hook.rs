@@ -0,0 +1,579 @@
+//! Run the hooks a profile declares, in the workspace it is built into.
+//!
+//! Hooks run with the target dir, or their `dir` in it, as working dir, and
+//! learn about the build from the environment:
+//!
+//! - `WSINIT_PROFILE`: name of the profile.
+//! - `WSINIT_PROFILE_VERSION`: its `version`, if it has one.
+//! - `WSINIT_TARGET`: absolute path of the target dir.
+//! - `WSINIT_PARAM_<NAME>`: value of every template variable, the name upper
+//!   cased, with anything but letters, digits and `_` turned into `_`.
+//! - `WSINIT_FILE`: for `post-file` hooks, the file, relative to the
+//!   working dir.
+//!
+//! Scripts are taken from the rendered profile, not from the workspace, so
+//! a script the build did not write because of a conflict still runs as the
+//! profile has it. They are written to a fresh temporary dir only the user
+//! can access, each run to a new file, so nothing can be swapped in between
+//! writing a script and running it.
+
+use std::{
+    cell::{Cell, RefCell},
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use ignore::gitignore::GitignoreBuilder;
+use profile::{
+    meta::{Hook, OnFailure, Stage},
+    template::{self, Vars},
+    DirRoot,
+};
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+
+use crate::plan;
+
+pub const INIT_SCRIPT_NAME: &str = "init.sh";
+const SHEBANG: &str = "shebang";
+const DEFAULT_INTERPRETER: &str = "sh";
+
+/// Why a build stops because of a hook.
+pub struct Failure {
+    pub on_failure: OnFailure,
+}
+
+pub struct Runner<'a> {
+    hooks: Vec<Hook>,
+    root: &'a DirRoot,
+    target: PathBuf,
+    env: Vec<(String, String)>,
+    /// Where scripts are written to run them, made by the first one.
+    scripts: RefCell<Option<TempDir>>,
+    /// Scripts written so far, to give each a file of its own.
+    written: Cell<u32>,
+}
+
+/// The hooks of a profile, or for a profile that declares none, its
+/// top level `init.sh` as a `post-build` hook.
+pub fn hooks_of(declared: &[Hook], root: &DirRoot) -> Vec<Hook> {
+    if !declared.is_empty() || !root.files.iter().any(|f| f.name == INIT_SCRIPT_NAME) {
+        return declared.to_vec();
+    }
+    vec![Hook {
+        stage: Stage::PostBuild,
+        script: Some(INIT_SCRIPT_NAME.to_string()),
+        run: None,
+        interpreter: None,
+        args: vec![],
+        timeout: None,
+        on_failure: OnFailure::Abort,
+        files: None,
+        dir: None,
+    }]
+}
+
+/// sha256 over the declarations of `hooks` and the scripts they run from
+/// `root`, the profile as stored, so it stays the same whatever the build's
+/// parameters are.
+pub fn digest(hooks: &[Hook], root: &DirRoot) -> String {
+    let files = plan::files(root);
+    let mut hasher = Sha256::new();
+    for hook in hooks {
+        let decl = [
+            hook.stage.name(),
+            hook.script.as_deref().unwrap_or_default(),
+            hook.run.as_deref().unwrap_or_default(),
+            hook.interpreter.as_deref().unwrap_or_default(),
+            &hook.args.join("\0"),
+            hook.files.as_deref().unwrap_or_default(),
+            hook.dir.as_deref().unwrap_or_default(),
+        ];
+        for field in decl {
+            hasher.update(field.len().to_le_bytes());
+            hasher.update(field);
+        }
+        let script = script_path(hook).and_then(|path| files.get(&path).copied());
+        let content = script.map(|f| f.as_bytes()).unwrap_or_default();
+        hasher.update(content.len().to_le_bytes());
+        hasher.update(content);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Print what `hooks` would run, scripts in full, for the user to review.
+pub fn show(hooks: &[Hook], root: &DirRoot) {
+    let files = plan::files(root);
+    for hook in hooks {
+        let interpreter = hook.interpreter.as_deref().unwrap_or(DEFAULT_INTERPRETER);
+        match (&hook.run, script_path(hook)) {
+            (Some(run), _) => println!(
+                "{} hook, runs with {}: {}",
+                hook.stage.name(),
+                interpreter,
+                run
+            ),
+            (None, Some(path)) => {
+                print!(
+                    "{} hook, runs {} with {}",
+                    hook.stage.name(),
+                    path,
+                    interpreter
+                );
+                match hook.args.is_empty() {
+                    true => println!(),
+                    false => println!(", args: {}", hook.args.join(" ")),
+                }
+                println!("{}", "-".repeat(30));
+                match files.get(&path) {
+                    Some(f) => println!("{}", String::from_utf8_lossy(f.as_bytes()).trim_end()),
+                    None => println!("(the profile has no {})", path),
+                }
+                println!("{}", "-".repeat(30));
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+/// Kill `child` and every process in its group.
+fn kill_group(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: kill only sends a signal, the group is the one the child
+        // was spawned into and is not reaped yet.
+        if unsafe { libc::kill(-pid, libc::SIGKILL) } == 0 {
+            return;
+        }
+    }
+    let _ = child.kill();
+}
+
+/// Path of the script of `hook` in the profile.
+fn script_path(hook: &Hook) -> Option<String> {
+    let script = hook.script.as_deref()?.trim_start_matches("./");
+    Some(match &hook.dir {
+        Some(dir) => format!("{}/{}", dir, script),
+        None => script.to_string(),
+    })
+}
+
+/// Environment describing a build, for the hooks it runs.
+pub fn env(
+    profile: &str,
+    version: Option<&str>,
+    target: &Path,
+    vars: &Vars,
+) -> Vec<(String, String)> {
+    let target = fs::canonicalize(target).unwrap_or(target.to_path_buf());
+    let mut env = vec![
+        ("WSINIT_PROFILE".to_string(), profile.to_string()),
+        ("WSINIT_TARGET".to_string(), target.display().to_string()),
+    ];
+    if let Some(version) = version {
+        env.push(("WSINIT_PROFILE_VERSION".to_string(), version.to_string()));
+    }
+
+    let mut names: Vec<&String> = vars.keys().collect();
+    names.sort();
+    for name in names {
+        let key: String = name
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect();
+        env.push((format!("WSINIT_PARAM_{}", key), vars[name].clone()));
+    }
+    env
+}
+
+impl<'a> Runner<'a> {
+    /// `hooks` as declared, their command lines and arguments are rendered
+    /// with `vars`. `root` is the rendered profile.
+    pub fn new(
+        hooks: &[Hook],
+        root: &'a DirRoot,
+        target: &Path,
+        vars: &Vars,
+        env: Vec<(String, String)>,
+    ) -> Self {
+        let hooks = hooks
+            .iter()
+            .map(|h| {
+                let mut h = h.clone();
+                h.run = h.run.map(|run| template::render(&run, vars));
+                h.args = h.args.iter().map(|a| template::render(a, vars)).collect();
+                h
+            })
+            .collect();
+        Self {
+            hooks,
+            root,
+            target: target.to_path_buf(),
+            env,
+            scripts: RefCell::new(None),
+            written: Cell::new(0),
+        }
+    }
+
+    /// Run the `pre-build` or `post-build` hooks, in order.
+    pub fn run_stage(&self, stage: Stage) -> Result<(), Failure> {
+        for hook in self.hooks.iter().filter(|h| h.stage == stage) {
+            self.run(hook, None)?;
+        }
+        Ok(())
+    }
+
+    /// Run the `post-file` hooks for each of `files`, relative to the
+    /// target dir.
+    pub fn run_post_file(&self, files: &[PathBuf]) -> Result<(), Failure> {
+        for hook in self.hooks.iter().filter(|h| h.stage == Stage::PostFile) {
+            let dir = Path::new(hook.dir.as_deref().unwrap_or_default());
+            let glob = match &hook.files {
+                Some(glob) => {
+                    let mut builder = GitignoreBuilder::new("");
+                    if let Err(err) = builder.add_line(None, glob) {
+                        return Err(self.fail(hook, format!("bad files glob: {}", err)));
+                    }
+                    match builder.build() {
+                        Ok(it) => Some(it),
+                        Err(err) => return Err(self.fail(hook, format!("bad files glob: {}", err))),
+                    }
+                }
+                None => None,
+            };
+
+            for file in files {
+                let rel = match file.strip_prefix(dir) {
+                    Ok(it) => it,
+                    Err(_) => continue,
+                };
+                if let Some(glob) = &glob {
+                    if !glob.matched_path_or_any_parents(rel, false).is_ignore() {
+                        continue;
+                    }
+                }
+                self.run(hook, Some(rel))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the scripts written to run hooks.
+    pub fn clean_up(&self) {
+        if let Some(dir) = self.scripts.borrow_mut().take() {
+            let _ = dir.close();
+        }
+    }
+
+    /// Run one hook, a failure only counts if the hook does not ignore it.
+    fn run(&self, hook: &Hook, file: Option<&Path>) -> Result<(), Failure> {
+        match self.exec(hook, file) {
+            Err(failure) if failure.on_failure != OnFailure::Ignore => Err(failure),
+            _ => Ok(()),
+        }
+    }
+
+    fn exec(&self, hook: &Hook, file: Option<&Path>) -> Result<(), Failure> {
+        let dir = self.target.join(hook.dir.as_deref().unwrap_or_default());
+        let cwd = match dir.is_dir() {
+            true => dir,
+            false => self.target.clone(),
+        };
+
+        let mut cmd = match (&hook.script, &hook.run) {
+            (Some(_), _) => {
+                let path = match self.write_script(hook) {
+                    Ok(it) => it,
+                    Err(err) => return Err(self.fail(hook, err)),
+                };
+                let mut cmd = match hook.interpreter.as_deref() {
+                    Some(SHEBANG) => Command::new(&path),
+                    interpreter => {
+                        let mut cmd = Command::new(interpreter.unwrap_or(DEFAULT_INTERPRETER));
+                        cmd.arg(&path);
+                        cmd
+                    }
+                };
+                cmd.args(&hook.args);
+                if let Some(file) = file {
+                    cmd.arg(file);
+                }
+                cmd
+            }
+            (None, run) => {
+                let mut cmd =
+                    Command::new(hook.interpreter.as_deref().unwrap_or(DEFAULT_INTERPRETER));
+                cmd.arg("-c").arg(run.as_deref().unwrap_or_default());
+                cmd
+            }
+        };
+        cmd.current_dir(&cwd)
+            .envs(self.env.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::inherit());
+        if let Some(file) = file {
+            cmd.env("WSINIT_FILE", file);
+        }
+        // A hook that can time out gets a process group of its own, to stop
+        // what it started along with it. Others stay in the foreground group
+        // so they can still read from the terminal.
+        #[cfg(unix)]
+        if hook.timeout.is_some() {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
+
+        match file {
+            Some(file) => println!(
+                "Running {} hook: {} {}",
+                hook.stage.name(),
+                hook.describe(),
+                file.display()
+            ),
+            None => println!("Running {} hook: {}", hook.stage.name(), hook.describe()),
+        }
+        println!("{}", "-".repeat(30));
+
+        let mut child = match cmd.spawn() {
+            Ok(it) => it,
+            Err(err) => return Err(self.fail(hook, format!("failed to start: {}", err))),
+        };
+        let status = match hook.timeout {
+            Some(secs) => {
+                let deadline = Instant::now() + Duration::from_secs(secs);
+                loop {
+                    match child.try_wait() {
+                        Ok(Some(status)) => break status,
+                        Ok(None) if Instant::now() < deadline => {
+                            thread::sleep(Duration::from_millis(50))
+                        }
+                        Ok(None) => {
+                            kill_group(&mut child);
+                            let _ = child.wait();
+                            return Err(self.fail(hook, format!("timed out after {}s", secs)));
+                        }
+                        Err(err) => return Err(self.fail(hook, err.to_string())),
+                    }
+                }
+            }
+            None => match child.wait() {
+                Ok(it) => it,
+                Err(err) => return Err(self.fail(hook, err.to_string())),
+            },
+        };
+
+        match status.success() {
+            true => Ok(()),
+            false => Err(self.fail(hook, status.to_string())),
+        }
+    }
+
+    /// Report a failed hook.
+    fn fail(&self, hook: &Hook, cause: String) -> Failure {
+        let level = match hook.on_failure {
+            OnFailure::Ignore => "W",
+            _ => "E",
+        };
+        eprintln!(
+            "{}: {} hook {} failed: {}",
+            level,
+            hook.stage.name(),
+            hook.describe(),
+            cause
+        );
+        Failure {
+            on_failure: hook.on_failure,
+        }
+    }
+
+    /// Write the profile's `script` into the scripts dir, to run it from
+    /// there.
+    fn write_script(&self, hook: &Hook) -> Result<PathBuf, String> {
+        let path = script_path(hook).unwrap_or_default();
+        let files = plan::files(self.root);
+        let f = match files.get(&path) {
+            Some(it) => it,
+            None => return Err(format!("the profile has no {}", path)),
+        };
+
+        let mut scripts = self.scripts.borrow_mut();
+        let dir = match scripts.as_ref() {
+            Some(it) => it,
+            None => match scripts_dir() {
+                Ok(it) => scripts.insert(it),
+                Err(err) => return Err(err.to_string()),
+            },
+        };
+        let n = self.written.get() + 1;
+        self.written.set(n);
+        let dest = dir.path().join(format!("{}-{}", n, path.replace('/', "_")));
+        match write_new_executable(&dest, f.as_bytes()) {
+            Ok(_) => Ok(dest),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+/// A new dir for scripts, that only the user can access.
+fn scripts_dir() -> Result<TempDir, io::Error> {
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("wsinit-hooks-");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(fs::Permissions::from_mode(0o700));
+    }
+    builder.tempdir()
+}
+
+/// Write `content` to a new file at `path`, failing if anything is there
+/// already, a link included.
+fn write_new_executable(path: &Path, content: &[u8]) -> Result<(), io::Error> {
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(0o700);
+    }
+    io::Write::write_all(&mut opts.open(path)?, content)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use profile::{FileContent, FileInfo, FileType};
+
+    use super::*;
+
+    fn script(name: &str, content: &str) -> FileInfo {
+        FileInfo::new(name, FileType::Text, FileContent::Text(content.to_string()))
+    }
+
+    fn run(stage: Stage, run: &str) -> Hook {
+        Hook {
+            stage,
+            script: None,
+            run: Some(run.to_string()),
+            interpreter: None,
+            args: vec![],
+            timeout: None,
+            on_failure: OnFailure::Abort,
+            files: None,
+            dir: None,
+        }
+    }
+
+    #[test]
+    fn init_script_runs_in_the_target_with_the_build_env() {
+        let target = tempfile::tempdir().unwrap();
+        let mut root = DirRoot::new("");
+        root.files.push(script(
+            INIT_SCRIPT_NAME,
+            "pwd > cwd\nenv | grep ^WSINIT_ | sort > env\nexit 3\n",
+        ));
+        let vars = Vars::from([("project-name".to_string(), "demo".to_string())]);
+        let env = env("rust", Some("1.2"), target.path(), &vars);
+        let hooks = hooks_of(&[], &root);
+        let runner = Runner::new(&hooks, &root, target.path(), &vars, env);
+
+        let failure = runner.run_stage(Stage::PostBuild).err().unwrap();
+        runner.clean_up();
+        assert!(failure.on_failure == OnFailure::Abort);
+
+        let target = fs::canonicalize(target.path()).unwrap();
+        let cwd = fs::read_to_string(target.join("cwd")).unwrap();
+        assert_eq!(cwd.trim_end(), target.display().to_string());
+        let env = fs::read_to_string(target.join("env")).unwrap();
+        assert_eq!(
+            env,
+            format!(
+                "WSINIT_PARAM_PROJECT_NAME=demo\nWSINIT_PROFILE=rust\n\
+                 WSINIT_PROFILE_VERSION=1.2\nWSINIT_TARGET={}\n",
+                target.display()
+            )
+        );
+    }
+
+    #[test]
+    fn declared_hooks_replace_the_init_script() {
+        let mut root = DirRoot::new("");
+        assert!(hooks_of(&[], &root).is_empty());
+        root.files.push(script(INIT_SCRIPT_NAME, "exit 0\n"));
+        let hooks = hooks_of(&[], &root);
+        assert_eq!(hooks[0].script.as_deref(), Some(INIT_SCRIPT_NAME));
+        assert_eq!(hooks[0].stage, Stage::PostBuild);
+
+        let declared = [run(Stage::PreBuild, "true")];
+        let hooks = hooks_of(&declared, &root);
+        assert_eq!(hooks.len(), 1);
+        assert_eq!(hooks[0].run.as_deref(), Some("true"));
+    }
+
+    fn runner<'a>(hooks: &[Hook], root: &'a DirRoot, target: &Path) -> Runner<'a> {
+        Runner::new(hooks, root, target, &Vars::new(), vec![])
+    }
+
+    #[test]
+    fn hooks_run_in_their_dir() {
+        let target = tempfile::tempdir().unwrap();
+        fs::create_dir_all(target.path().join("web/src")).unwrap();
+        let mut root = DirRoot::new("");
+        let mut web = DirRoot::new("web");
+        web.files.push(script("setup.sh", "pwd > setup.out\n"));
+        root.dirs.push(web);
+
+        let mut setup = run(Stage::PostBuild, "");
+        setup.run = None;
+        setup.script = Some("./setup.sh".to_string());
+        setup.dir = Some("web".to_string());
+        let mut each = run(Stage::PostFile, "echo \"$WSINIT_FILE $1\" >> files.out");
+        each.dir = Some("web".to_string());
+        let runner = runner(&[setup, each], &root, target.path());
+
+        assert!(runner.run_stage(Stage::PostBuild).is_ok());
+        let files = [PathBuf::from("web/src/app.js"), PathBuf::from("README")];
+        assert!(runner.run_post_file(&files).is_ok());
+        runner.clean_up();
+
+        let web = fs::canonicalize(target.path().join("web")).unwrap();
+        let cwd = fs::read_to_string(web.join("setup.out")).unwrap();
+        assert_eq!(cwd.trim_end(), web.display().to_string());
+        let files = fs::read_to_string(web.join("files.out")).unwrap();
+        assert_eq!(files, "src/app.js \n");
+    }
+
+    #[test]
+    fn hooks_that_run_too_long_are_stopped() {
+        let target = tempfile::tempdir().unwrap();
+        let root = DirRoot::new("");
+        let mut hook = run(Stage::PreBuild, "(sleep 2; touch late) & sleep 10");
+        hook.timeout = Some(1);
+        let runner = runner(&[hook], &root, target.path());
+
+        let start = Instant::now();
+        assert!(runner.run_stage(Stage::PreBuild).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // What the hook started is stopped with it.
+        thread::sleep(Duration::from_secs(2));
+        assert!(!target.path().join("late").exists());
+    }
+
+    #[test]
+    fn ignored_failures_do_not_stop_the_build() {
+        let target = tempfile::tempdir().unwrap();
+        let root = DirRoot::new("");
+        let mut ignored = run(Stage::PreBuild, "exit 2");
+        ignored.on_failure = OnFailure::Ignore;
+        let mut rollback = run(Stage::PreBuild, "exit 5");
+        rollback.on_failure = OnFailure::Rollback;
+        let runner = runner(&[ignored, rollback], &root, target.path());
+
+        let failure = runner.run_stage(Stage::PreBuild).err().unwrap();
+        assert!(failure.on_failure == OnFailure::Rollback);
+    }
+}
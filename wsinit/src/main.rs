@@ -1,18 +1,47 @@
-use clap::{command, Parser};
-use paths::{get_os_config_dir, get_os_dir_sep, get_profile_path};
-use profile::DirRoot;
-use settings::{Settings, Wsinit};
+use clap::{Parser, Subcommand};
+use hook::{Failure, Runner};
+use manifest::{to_slash, Build, FileRecord, Manifest};
+use paths::{get_profile_file_name, resolve_config_dir};
+use plan::{paths_in, plan, Action, Entry, State, Step};
+use profile::{
+    format,
+    limits::Limits,
+    meta::{Hook, OnFailure, Param, ProfileMeta, Stage},
+    template::{self, Vars},
+    DirRoot, Profile,
+};
+use settings::{
+    roots::{Listed, Roots},
+    ConflictPolicy, Settings, Wsinit,
+};
 use std::{
     fs::{self, create_dir_all, File},
-    process::{exit, Stdio},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::exit,
+    sync::OnceLock,
 };
+use transaction::{Built, Transaction};
+use undo::UndoPlan;
+use upgrade::{Change, Op, Outcome, REJECT_SUFFIX};
+
+mod conflict;
+#[cfg_attr(not(feature = "init_script"), allow(dead_code))]
+mod hook;
+mod manifest;
+mod merge;
+mod plan;
+mod status;
+mod transaction;
+mod undo;
+mod upgrade;
 
 /// Init workspace by profile file.
 #[derive(Parser)]
 #[command(author = "lI15SO0", version, about)]
 struct Args {
     /// Name of profile.
-    #[arg(short = 'c', long)]
+    #[arg(short = 'c', long, global = true)]
     profile: Option<String>,
 
     /// Where to init. (default: ./)
@@ -23,22 +52,96 @@ struct Args {
     #[arg(short, long)]
     print: bool,
 
-    /// Force mode.
+    /// Force mode, same as --on-conflict overwrite.
     #[arg(short, long)]
     force: bool,
 
+    /// What to do with existing files: abort, skip, overwrite, backup, rename or prompt.
+    #[arg(long, value_name = "POLICY")]
+    on_conflict: Option<ConflictPolicy>,
+
     /// List of profiles.
     #[arg(short, long)]
     list: bool,
 
-    /// Don't run init.sh
+    /// Don't run the profile's hooks or init.sh
     #[cfg(feature = "init_script")]
     #[arg(short = 'r', long)]
     no_init: bool,
 
+    /// Run the profile's hooks without asking, even if not trusted yet.
+    #[cfg(feature = "init_script")]
+    #[arg(long, conflicts_with = "no_init")]
+    trust: bool,
+
     /// Set default profile.
     #[arg(short, long = "set-default")]
     setdefault: bool,
+
+    /// Give a value to a profile parameter. Can be repeated.
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    set: Vec<String>,
+
+    /// Read profile parameters from a toml file.
+    #[arg(long, value_name = "FILE", global = true)]
+    answers: Option<String>,
+
+    /// Record the build in .wsinit/manifest of the workspace.
+    #[arg(short, long, global = true)]
+    manifest: bool,
+
+    /// Don't record the build, even if the settings ask to.
+    #[arg(long, global = true, conflicts_with = "manifest")]
+    no_manifest: bool,
+
+    /// Build links of the profile that point outside of the target dir.
+    #[arg(long, global = true)]
+    allow_external_links: bool,
+
+    /// Where profiles and settings are kept, instead of $WSINIT_HOME or the
+    /// default config dir.
+    #[arg(long, value_name = "DIR", global = true)]
+    config_dir: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show how a workspace differs from what wsinit built into it.
+    Status {
+        /// Workspace to check. (default: ./)
+        dir: Option<String>,
+    },
+    /// Upgrade a workspace to the current version of its profile, keeping
+    /// local changes.
+    Upgrade {
+        /// Workspace to upgrade. (default: ./)
+        dir: Option<String>,
+    },
+    /// Remove what a build created from a workspace.
+    Undo {
+        /// Workspace to undo the build in. (default: ./)
+        dir: Option<String>,
+
+        /// Id of the build to undo. (default: the last one)
+        #[arg(short, long, value_name = "ID")]
+        build: Option<u32>,
+
+        /// Remove files even if they changed since the build.
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+/// Where a build comes from, recorded in the workspace manifest and told to
+/// the scripts it runs.
+struct Origin {
+    profile: String,
+    version: Option<String>,
+    hash: String,
+    vars: Vars,
 }
 
 const SETTING_NAME: &str = "settings.toml";
@@ -46,10 +149,44 @@ const SETTING_NAME: &str = "settings.toml";
 const PROFILES_DIR_NAME: &str = "profiles";
 const _PROFILE_SUFFIX: &str = ".bincode";
 
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Find the config dir, `flag` first, or end the process if there is none.
+fn init_config_dir(flag: Option<PathBuf>) {
+    let dir = resolve_config_dir(flag).unwrap_or_else(|err| {
+        eprintln!("E: {}", err);
+        exit(err.exit_code());
+    });
+    let _ = CONFIG_DIR.set(dir);
+}
+
+fn config_dir() -> &'static Path {
+    CONFIG_DIR.get().expect("config dir is set at startup")
+}
+
+/// Where profiles are looked up, or the end of the process if the settings
+/// list them wrong.
+fn profile_roots(settings: &Settings) -> Roots {
+    settings.roots(config_dir()).unwrap_or_else(|err| {
+        eprintln!("E: {}", err);
+        exit(err.exit_code());
+    })
+}
+
+/// Where the profile called `name` is stored, in the first root that has it.
+/// Profiles that don't exist are looked for in the user store.
+fn profile_path(settings: &Settings, name: &str) -> PathBuf {
+    let roots = profile_roots(settings);
+    match roots.find(name) {
+        Some((_, path)) => path,
+        None => roots.user().path.join(get_profile_file_name(name)),
+    }
+}
+
 fn ensure_dirs() -> Result<(), std::io::Error> {
-    let p = get_os_config_dir() + PROFILES_DIR_NAME;
+    let p = config_dir().join(PROFILES_DIR_NAME);
     if File::open(&p).is_err() {
-        let _ = create_dir_all(p)?;
+        create_dir_all(p)?;
     }
     Ok(())
 }
@@ -57,25 +194,30 @@ fn ensure_dirs() -> Result<(), std::io::Error> {
 fn main() {
     let args = Args::parse();
     multi_operation_checker(&args);
+    init_config_dir(args.config_dir.clone());
 
     let settings = {
-        let path = get_os_config_dir() + SETTING_NAME;
+        let path = config_dir().join(SETTING_NAME);
         match ensure_dirs() {
             Ok(_) => {}
             Err(err) => {
                 eprintln!("E: Failed to init config dir: {}", err);
-                exit(1)
+                exit(exit_code::of_io(&err))
             }
         };
-        Settings::read_from(&path).unwrap_or_else(|err| {
-            if err.0 == 1 {
+        Settings::read_from(&path).unwrap_or_else(|err| match err {
+            settings::Error::NotFound(_) => {
                 let settings = Settings {
                     wsinit: Wsinit::new(),
                 };
-                settings.write(&path).expect("E: Failed to init settings.");
+                if let Err(err) = settings.write(&path) {
+                    eprintln!("E: Failed to init settings. cause: {}", err);
+                    exit(err.exit_code());
+                }
                 settings
-            } else {
-                eprintln!("W: Error in reading settings cause: {}", err.1);
+            }
+            err => {
+                eprintln!("W: Error in reading settings cause: {}", err);
                 Settings {
                     wsinit: Wsinit::new(),
                 }
@@ -83,8 +225,24 @@ fn main() {
         })
     };
 
+    match &args.command {
+        Some(Command::Status { dir }) => {
+            print_status(dir.as_deref().unwrap_or("./"));
+            exit(0);
+        }
+        Some(Command::Upgrade { dir }) => {
+            upgrade_workspace(&settings, &args, dir.as_deref().unwrap_or("./"));
+            exit(0);
+        }
+        Some(Command::Undo { dir, build, force }) => {
+            undo_build(dir.as_deref().unwrap_or("./"), *build, *force);
+            exit(0);
+        }
+        None => {}
+    }
+
     if args.list {
-        print_all_profiles();
+        print_all_profiles(&settings);
         exit(0);
     }
 
@@ -94,7 +252,7 @@ fn main() {
     }
 
     if args.print {
-        show_profile_content(args);
+        show_profile_content(&settings, args);
         exit(0);
     }
 
@@ -117,8 +275,8 @@ fn multi_operation_checker(args: &Args) {
     }
 
     if multi_checker > 1 {
-        println!("Pls do not use multi operation args.");
-        exit(1);
+        eprintln!("Pls do not use multi operation args.");
+        exit(exit_code::USAGE);
     }
 }
 
@@ -131,128 +289,379 @@ fn build_workspace(settings: Settings, args: Args) {
         .profile
         .clone()
         .unwrap_or(get_default_profile(&settings));
-    if profile_name == "" {
-        println!("E: Not give a profile name, and not set default profile.");
-        exit(0);
+    if profile_name.is_empty() {
+        eprintln!("E: Not give a profile name, and not set default profile.");
+        exit(exit_code::USAGE);
     }
 
-    let dir_root = {
-        let profile_path = get_profile_path(profile_name.clone());
-        DirRoot::read_from(&profile_path).unwrap_or_else(|err| {
-            eprintln!("E: Failed to read profile. cause: {}", err);
-            exit(1)
-        })
+    let profile_path = profile_path(&settings, &profile_name);
+    let limits = profile_limits(&settings, &args);
+    let profile = Profile::read_with_limits(&profile_path, &limits).unwrap_or_else(|err| {
+        eprintln!("E: Failed to read profile. cause: {}", err);
+        exit(err.exit_code())
+    });
+
+    #[cfg(feature = "init_script")]
+    let (hooks, settings) = {
+        let mut settings = settings;
+        let hooks = trusted_hooks(&mut settings, &args, &profile_name, &profile);
+        (hooks, settings)
     };
+    #[cfg(not(feature = "init_script"))]
+    let hooks: Vec<Hook> = vec![];
 
     let target = &args.target.clone().unwrap_or("./".to_string());
 
-    build_workspace_from_root(dir_root, &target, &args);
+    let vars = resolve_vars(
+        &profile.meta,
+        &args,
+        builtin_vars(&profile_name, target),
+        Vars::new(),
+    );
+    let dir_root = render_profile(profile.root, &vars, &limits);
+    let bootstrap = bootstrap_files(&profile.meta, &vars);
+
+    let origin = Origin {
+        profile: profile_name.trim_end_matches(".bincode").to_string(),
+        version: profile.meta.version.clone(),
+        hash: fs::read(&profile_path)
+            .map(|b| manifest::hash(&b))
+            .unwrap_or_default(),
+        vars,
+    };
+    let record = args.manifest || (!args.no_manifest && settings.wsinit.get_manifest());
+
+    let policy = args
+        .on_conflict
+        .or(args.force.then_some(ConflictPolicy::Overwrite))
+        .or(settings.wsinit.get_on_conflict())
+        .unwrap_or(ConflictPolicy::Abort);
+
+    build_workspace_from_root(
+        dir_root, target, policy, &origin, record, &hooks, &bootstrap,
+    );
 }
 
-fn build_workspace_from_root(dir_root: DirRoot, target: &str, args: &Args) {
-    if !args.force {
-        check_repeat(&dir_root, target);
+fn build_workspace_from_root(
+    dir_root: DirRoot,
+    target: &str,
+    policy: ConflictPolicy,
+    origin: &Origin,
+    record: bool,
+    hooks: &[Hook],
+    bootstrap: &[String],
+) {
+    let target = Path::new(target);
+    let mut steps = plan(&dir_root, target);
+    check_conflicts(&steps, policy);
+    conflict::resolve(&mut steps, target, policy);
+
+    let mut tx = Transaction::begin(target).unwrap_or_else(|err| {
+        eprintln!("E: Failed to prepare {}: {}", target.display(), err);
+        exit(exit_code::of_io(&err));
+    });
+    let runner = hook_runner(hooks, &dir_root, target, origin);
+
+    if let Some(runner) = &runner {
+        if runner.run_stage(Stage::PreBuild).is_err() {
+            // Nothing is written yet, so there is nothing to keep.
+            runner.clean_up();
+            rollback(tx);
+            exit(exit_code::HOOK_FAILED);
+        }
     }
 
-    let target = {
-        if target.ends_with('/') || target.ends_with('\\') {
-            target.to_string()
-        } else {
-            target.to_string() + &get_os_dir_sep()
+    let built = match tx.apply(&steps) {
+        Ok(it) => it,
+        Err(err) => {
+            eprintln!("E: An error has occupied when create dirs: {}", err);
+            rollback(tx);
+            exit(exit_code::of_io(&err));
         }
     };
+    print_built(&steps, &built, target);
 
-    fn _build(dirs: &DirRoot, prefix: &str) -> Result<(), std::io::Error> {
-        for f in &dirs.dirs {
-            fs::create_dir_all(prefix.to_string() + &f.name)?;
-            println!("Created dir: {}", prefix.to_string() + &f.name);
-            _build(
-                &f,
-                &(prefix.to_string() + &f.name.clone() + &get_os_dir_sep()),
-            )?;
-        }
-
-        for f in &dirs.files {
-            let size = f.write(&(prefix.to_string() + &f.name))?;
-            println!(
-                "Created file: {} , size: {}",
-                prefix.to_string() + &f.name,
-                size
-            );
+    if let Some(runner) = &runner {
+        if let Err(failure) = runner.run_post_file(&built.files) {
+            stop_build(tx, runner, failure);
+        }
+    }
+
+    if let Some(runner) = &runner {
+        if let Err(failure) = runner.run_stage(Stage::PostBuild) {
+            stop_build(tx, runner, failure);
         }
-        Ok(())
+        runner.clean_up();
     }
 
-    match _build(&dir_root, &target) {
-        Ok(_) => {}
+    let (stripped, stripped_dirs) = match strip_bootstrap(&mut tx, &steps, &built, bootstrap) {
+        Ok(it) => it,
         Err(err) => {
-            eprintln!(
-                "E: An error has occupied when create dirs: {}",
-                err.to_string()
-            );
-            exit(1);
+            eprintln!("E: Failed to remove bootstrap files: {}", err);
+            rollback(tx);
+            exit(exit_code::of_io(&err));
         }
     };
+    for path in &stripped {
+        println!("Removed bootstrap file: {}", target.join(path).display());
+    }
 
-    #[cfg(feature = "init_script")]
-    {
-        if !args.no_init {
-            if dir_root.files.iter().fold(false, |has, f| {
-                if f.name == "init.sh" {
-                    println!("Detected init.sh, Running init script.");
-                    println!("{}", "-".repeat(30));
-                    true
-                } else {
-                    has
-                }
-            }) {
-                match std::process::Command::new("sh")
-                    .arg("init.sh")
-                    .stdin(Stdio::inherit())
-                    .status()
-                {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("E: {}", e);
-                    }
-                }
+    if record {
+        let mut build = Build::new(&steps);
+        build.files.retain(|f| !stripped.contains(&f.path));
+        build.dirs.retain(|d| !stripped_dirs.contains(d));
+        let mut base = dir_root.clone();
+        plan::remove_files(&mut base, bootstrap);
+
+        match record_build(&mut tx, build, origin, &base, target) {
+            Ok(id) => println!(
+                "Recorded build {} in {}",
+                id,
+                Manifest::path(target).display()
+            ),
+            Err(err) => {
+                eprintln!("E: Failed to write manifest: {}", err);
+                rollback(tx);
+                exit(exit_code::FAILURE);
             }
         }
     }
+
+    if let Err(err) = tx.finish() {
+        eprintln!("W: Failed to clean up staging dir: {}", err);
+    }
 }
 
-fn check_repeat(dir_root: &DirRoot, target: &str) {
-    println!("Checking repats.");
-    let lst_of_dir_root = {
-        let mut dir_lst: Vec<String> = dir_root.dirs.iter().map(|d| d.name.clone()).collect();
-        let mut file_lst: Vec<String> = dir_root.files.iter().map(|f| f.name.clone()).collect();
-        dir_lst.append(&mut file_lst);
-        dir_lst
-    };
+/// Remove the bootstrap files the build wrote, putting back what they
+/// replaced, and the dirs above them it created that are empty without
+/// them. Returns the paths of both.
+fn strip_bootstrap(
+    tx: &mut Transaction,
+    steps: &[Step],
+    built: &Built,
+    bootstrap: &[String],
+) -> Result<(Vec<String>, Vec<String>), io::Error> {
+    let mut files = vec![];
+    let mut parents = vec![];
+    for step in steps {
+        let path = to_slash(&step.path);
+        if matches!(step.entry, Entry::Dir) || !bootstrap.contains(&path) {
+            continue;
+        }
+        match &step.action {
+            Action::Keep => continue,
+            Action::Write => tx.remove_file(&step.path)?,
+            Action::WriteAs(written) => tx.remove_file(written)?,
+            Action::Backup(backup) => {
+                tx.remove_file(&step.path)?;
+                tx.rename(backup, &step.path)?;
+            }
+        }
+        files.push(path);
+        parents.extend(step.path.ancestors().skip(1));
+    }
 
-    let mut repeat_flag = false;
+    // Deepest first, so a dir is only looked at once those in it are gone.
+    // Dirs the profile has empty on purpose are not above a stripped file.
+    let mut dirs = vec![];
+    for dir in built.dirs.iter().rev() {
+        if parents.contains(&dir.as_path()) && undo::is_empty_dir(&tx.target().join(dir)) {
+            tx.remove_dir(dir)?;
+            dirs.push(to_slash(dir));
+        }
+    }
+    Ok((files, dirs))
+}
 
-    let mut repeat: Vec<String> = vec![];
-    for entry in fs::read_dir(target).unwrap() {
-        let f_name = {
-            let entry = entry.unwrap();
-            entry.file_name().to_str().unwrap().to_string()
-        };
-        if lst_of_dir_root.contains(&f_name) {
-            repeat_flag = true;
-            repeat.push(f_name);
-        };
+fn hook_runner<'a>(
+    hooks: &[Hook],
+    root: &'a DirRoot,
+    target: &Path,
+    origin: &Origin,
+) -> Option<Runner<'a>> {
+    if hooks.is_empty() {
+        return None;
+    }
+    let env = hook::env(
+        &origin.profile,
+        origin.version.as_deref(),
+        target,
+        &origin.vars,
+    );
+    Some(Runner::new(hooks, root, target, &origin.vars, env))
+}
+
+/// The hooks of `profile` to run: none with `--no-init`, and otherwise
+/// only once the user trusts them. Hooks the user approved are remembered
+/// in the settings until they change.
+#[cfg(feature = "init_script")]
+fn trusted_hooks(
+    settings: &mut Settings,
+    args: &Args,
+    profile_name: &str,
+    profile: &Profile,
+) -> Vec<Hook> {
+    let hooks = hook::hooks_of(&profile.meta.hooks, &profile.root);
+    if args.no_init || hooks.is_empty() {
+        return vec![];
+    }
+    let name = profile_name.trim_end_matches(".bincode");
+    let digest = hook::digest(&hooks, &profile.root);
+    if args.trust || settings.wsinit.is_trusted(name, &digest) {
+        return hooks;
+    }
+
+    if !io::stdin().is_terminal() {
+        eprintln!(
+            "E: Profile {} runs hooks that are not trusted yet, review them in a terminal, or use --trust or --no-init.",
+            name
+        );
+        exit(exit_code::USAGE);
+    }
+    println!("Profile {} wants to run these hooks:", name);
+    hook::show(&hooks, &profile.root);
+    loop {
+        print!("Run them? [y]es and remember, [n]o and build without them, [q]uit: ");
+        let _ = io::stdout().flush();
+
+        let buf = read_answer();
+        match buf.trim().to_lowercase().as_str() {
+            "y" => break,
+            "n" => return vec![],
+            "q" => {
+                println!("Give up!");
+                exit(exit_code::FAILURE);
+            }
+            _ => {}
+        }
+    }
+
+    settings.wsinit.trust(name, &digest);
+    if let Err(err) = settings.write(config_dir().join(SETTING_NAME)) {
+        eprintln!("W: Failed to remember the hooks as trusted: {}", err);
+    }
+    hooks
+}
+
+/// Stop the build after a hook failed, keeping or rolling back what it
+/// wrote as the hook asks.
+fn stop_build(tx: Transaction, runner: &Runner, failure: Failure) -> ! {
+    runner.clean_up();
+    settle(tx, failure.on_failure);
+    exit(exit_code::HOOK_FAILED);
+}
+
+/// Keep what a stopped build wrote, or take it back for `Rollback`.
+fn settle(tx: Transaction, on_failure: OnFailure) {
+    match on_failure {
+        OnFailure::Rollback => rollback(tx),
+        _ => {
+            if let Err(err) = tx.finish() {
+                eprintln!("W: Failed to clean up staging dir: {}", err);
+            }
+            eprintln!("Stopped, the workspace is kept as it is.");
+        }
+    }
+}
+
+/// Add `build` to the manifest of the workspace, and keep the rendered
+/// profile it wrote as the base of later upgrades. Returns its id.
+fn record_build(
+    tx: &mut Transaction,
+    mut build: Build,
+    origin: &Origin,
+    root: &DirRoot,
+    target: &Path,
+) -> Result<u32, String> {
+    let mut manifest = Manifest::read_from(target).map_err(|err| err.to_string())?;
+    build.id = manifest.next_id();
+    build.profile = origin.profile.clone();
+    build.profile_version = origin.version.clone();
+    build.profile_hash = origin.hash.clone();
+    build.params = origin.vars.clone().into_iter().collect();
+
+    let mut base = Profile::new(root.clone());
+    base.meta.version = origin.version.clone();
+    let base = format::encode(&base).map_err(|err| err.to_string())?;
+    let manifest_path = Manifest::path(Path::new(""));
+    let base_path = Manifest::base_path(Path::new(""), build.id);
+
+    let id = build.id;
+    manifest.builds.push(build);
+    let s = manifest.to_toml()?;
+    match tx
+        .write_file(&base_path, &base)
+        .and_then(|_| tx.write_file(&manifest_path, s.as_bytes()))
+    {
+        Ok(_) => Ok(id),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn rollback(tx: Transaction) {
+    match tx.rollback() {
+        Ok(_) => eprintln!("Rolled back, the target dir is as it was."),
+        Err(err) => eprintln!("E: Rollback was incomplete: {}", err),
+    }
+}
+
+fn print_built(steps: &[Step], built: &Built, target: &Path) {
+    for step in steps {
+        if step.state == State::Overwrite && step.action == Action::Keep {
+            println!("Skipped file: {}", target.join(&step.path).display());
+        }
+    }
+    for (path, backup) in &built.backups {
+        println!(
+            "Backed up: {} -> {}",
+            target.join(path).display(),
+            target.join(backup).display()
+        );
+    }
+    for path in &built.dirs {
+        println!("Created dir: {}", target.join(path).display());
+    }
+    for path in &built.files {
+        let path = target.join(path);
+        let size = fs::metadata(&path).map(|m| m.len()).unwrap_or_default();
+        println!("Created file: {} , size: {}", path.display(), size);
+    }
+}
+
+/// Report which files the profile would overwrite and which dirs already
+/// exist. Stops when files would be overwritten and the policy is to abort,
+/// and always when a file and a dir are in each other's way.
+fn check_conflicts(steps: &[Step], policy: ConflictPolicy) {
+    println!("Checking conflicts.");
+
+    let merged = paths_in(steps, State::Exists);
+    if !merged.is_empty() {
+        println!("Dirs already exist, merging into them:");
+        merged.iter().for_each(|s| println!("\t{}", s));
+    }
+
+    let overwrite = paths_in(steps, State::Overwrite);
+    if !overwrite.is_empty() {
+        eprintln!("Files would be overwritten:");
+        overwrite.iter().for_each(|s| eprintln!("\t{}", s));
     }
-    if repeat_flag {
-        eprintln!("Detected repeats:");
-        repeat.iter().for_each(|s| eprintln!("\t{}", s));
-        eprintln!("This dir has a repeat dir, stop build workspace.(use -f to force build).");
-        exit(1);
+
+    let blocked = paths_in(steps, State::Blocked);
+    if !blocked.is_empty() {
+        eprintln!("A file and a dir have the same name:");
+        blocked.iter().for_each(|s| eprintln!("\t{}", s));
+        eprintln!("Remove them from the target dir, stop build workspace.");
+        exit(exit_code::FAILURE);
+    }
+
+    if !overwrite.is_empty() && policy == ConflictPolicy::Abort {
+        eprintln!("This dir has files the profile would overwrite, stop build workspace.(use -f or --on-conflict to build anyway).");
+        exit(exit_code::FAILURE);
     }
     println!("Generated done!");
 }
 
-fn print_profiles_lst(profiles: &Vec<String>) {
+fn print_profiles_lst(profiles: &[String]) {
     if profiles.is_empty() {
         println!("Not have any profiles.");
     } else {
@@ -268,48 +677,666 @@ fn print_profiles_lst(profiles: &Vec<String>) {
 //   Builder:
 //------------------------------------------------------------------------------
 
+//==============================================================================
+//   :Status
+//==============================================================================
+
+fn print_status(dir: &str) {
+    let workspace = Path::new(dir);
+    let manifest = Manifest::read_from(workspace).unwrap_or_else(|err| {
+        eprintln!("E: Failed to read manifest. cause: {}", err);
+        exit(exit_code::of_io(&err));
+    });
+    let last = match manifest.builds.last() {
+        Some(it) => it,
+        None => {
+            eprintln!(
+                "E: {} has no record of a build, nothing to compare.",
+                Manifest::path(workspace).display()
+            );
+            exit(exit_code::NOT_FOUND);
+        }
+    };
+
+    let status = status::status(workspace, &manifest).unwrap_or_else(|err| {
+        eprintln!("E: Failed to read workspace. cause: {}", err);
+        exit(exit_code::of_io(&err));
+    });
+
+    print!("Built from profile {}", last.profile);
+    if let Some(version) = &last.profile_version {
+        print!(" {}", version);
+    }
+    println!(" (build {}).", last.id);
+    for (title, paths) in [
+        ("Unchanged", &status.unchanged),
+        ("Modified", &status.modified),
+        ("Deleted", &status.deleted),
+        ("Added", &status.added),
+    ] {
+        if !paths.is_empty() {
+            println!("{}:", title);
+            paths.iter().for_each(|p| println!("\t{}", p));
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+//   Status:
+//------------------------------------------------------------------------------
+
+//==============================================================================
+//   :Upgrade
+//==============================================================================
+
+fn upgrade_workspace(settings: &Settings, args: &Args, dir: &str) {
+    let workspace = Path::new(dir);
+    let manifest = Manifest::read_from(workspace).unwrap_or_else(|err| {
+        eprintln!("E: Failed to read manifest. cause: {}", err);
+        exit(exit_code::of_io(&err));
+    });
+    let live = manifest.live();
+    let profile_name = match (&args.profile, live.last()) {
+        (Some(name), _) => name.trim_end_matches(".bincode").to_string(),
+        (None, Some(last)) => last.profile.clone(),
+        (None, None) => {
+            eprintln!(
+                "E: {} has no record of a build, nothing to upgrade.",
+                Manifest::path(workspace).display()
+            );
+            exit(exit_code::NOT_FOUND);
+        }
+    };
+    let from = match live.iter().rev().find(|b| b.profile == profile_name) {
+        Some(it) => it,
+        None => {
+            eprintln!("E: No build of profile {} is recorded here.", profile_name);
+            exit(exit_code::NOT_FOUND);
+        }
+    };
+    let base_path = Manifest::base_path(workspace, from.id);
+    let limits = profile_limits(settings, args);
+    let base = Profile::read_with_limits(&base_path, &limits).unwrap_or_else(|err| {
+        eprintln!(
+            "E: Failed to read what build {} wrote from {}. cause: {}",
+            from.id,
+            base_path.display(),
+            err
+        );
+        exit(err.exit_code());
+    });
+
+    let profile_path = profile_path(settings, &profile_name);
+    let profile = Profile::read_with_limits(&profile_path, &limits).unwrap_or_else(|err| {
+        eprintln!("E: Failed to read profile. cause: {}", err);
+        exit(err.exit_code())
+    });
+    let vars = resolve_vars(
+        &profile.meta,
+        args,
+        builtin_vars(&profile_name, dir),
+        from.params.clone().into_iter().collect(),
+    );
+    let mut theirs = render_profile(profile.root, &vars, &limits);
+    plan::remove_files(&mut theirs, &bootstrap_files(&profile.meta, &vars));
+
+    let changes = upgrade::plan_upgrade(&base.root, &theirs, workspace).unwrap_or_else(|err| {
+        eprintln!("E: Failed to read workspace. cause: {}", err);
+        exit(exit_code::of_io(&err));
+    });
+
+    print!("Upgrading {} from {}", workspace.display(), profile_name);
+    if let Some(version) = &from.profile_version {
+        print!(" {}", version);
+    }
+    match &profile.meta.version {
+        Some(version) => println!(" to {}.", version),
+        None => println!("."),
+    }
+
+    let mut tx = Transaction::begin(workspace).unwrap_or_else(|err| {
+        eprintln!("E: Failed to prepare {}: {}", workspace.display(), err);
+        exit(exit_code::of_io(&err));
+    });
+    let mut build = Build::empty();
+    build.upgrade_of = Some(from.id);
+    if let Err(err) = apply_upgrade(&mut tx, &mut build, &theirs, &changes, workspace) {
+        eprintln!("E: Failed to upgrade: {}", err);
+        rollback(tx);
+        exit(exit_code::of_io(&err));
+    }
+
+    // The workspace has a manifest already, it is kept up to date.
+    if !args.no_manifest {
+        let origin = Origin {
+            profile: profile_name,
+            version: profile.meta.version.clone(),
+            hash: fs::read(&profile_path)
+                .map(|b| manifest::hash(&b))
+                .unwrap_or_default(),
+            vars,
+        };
+        if let Err(err) = record_build(&mut tx, build, &origin, &theirs, workspace) {
+            eprintln!("E: Failed to write manifest: {}", err);
+            rollback(tx);
+            exit(exit_code::FAILURE);
+        }
+    }
+    if let Err(err) = tx.finish() {
+        eprintln!("W: Failed to clean up staging dir: {}", err);
+    }
+
+    let mut conflicts = 0;
+    for change in &changes {
+        let what = match change.outcome {
+            Outcome::Unchanged => continue,
+            Outcome::Added => "Added",
+            Outcome::Updated => "Updated",
+            Outcome::Merged => "Merged",
+            Outcome::Conflict => {
+                conflicts += 1;
+                "Conflict"
+            }
+            Outcome::Removed => "Removed",
+            Outcome::KeptChanged => "Kept, removed from the profile but changed here",
+            Outcome::KeptDeleted => "Not restored, changed in the profile but deleted here",
+        };
+        match change.op {
+            Op::Reject(_) => println!(
+                "{}: {}, the profile's version is in {}{}",
+                what, change.path, change.path, REJECT_SUFFIX
+            ),
+            _ => println!("{}: {}", what, change.path),
+        }
+    }
+    if conflicts > 0 {
+        eprintln!(
+            "W: {} files have conflicts, resolve them by hand.",
+            conflicts
+        );
+        exit(exit_code::FAILURE);
+    }
+}
+
+fn apply_upgrade(
+    tx: &mut Transaction,
+    build: &mut Build,
+    theirs: &DirRoot,
+    changes: &[Change],
+    workspace: &Path,
+) -> Result<(), io::Error> {
+    for dir in plan::dirs(theirs) {
+        if !workspace.join(&dir).is_dir() {
+            tx.create_dir(Path::new(&dir))?;
+            build.dirs.push(dir);
+        }
+    }
+
+    for change in changes {
+        let path = Path::new(&change.path);
+        let mut written_to = None;
+        match &change.op {
+            Op::Keep => {}
+            Op::Write(bytes) if change.link => {
+                tx.write_link(path, &String::from_utf8_lossy(bytes))?
+            }
+            Op::Write(bytes) => tx.write_file_with_mode(path, bytes, change.mode)?,
+            Op::Remove => tx.remove_file(path)?,
+            Op::Reject(bytes) => {
+                let rej = format!("{}{}", change.path, REJECT_SUFFIX);
+                tx.write_file(Path::new(&rej), bytes)?;
+                written_to = Some(rej);
+            }
+        }
+        if let (Some(how), Some(hash)) = (change.how(), &change.hash) {
+            build.files.push(FileRecord {
+                path: change.path.clone(),
+                hash: hash.clone(),
+                how,
+                backup: None,
+                written_to,
+            });
+        }
+    }
+    Ok(())
+}
+
+//------------------------------------------------------------------------------
+//   Upgrade:
+//------------------------------------------------------------------------------
+
+//==============================================================================
+//   :Undo
+//==============================================================================
+
+fn undo_build(dir: &str, id: Option<u32>, force: bool) {
+    let workspace = Path::new(dir);
+    let mut manifest = Manifest::read_from(workspace).unwrap_or_else(|err| {
+        eprintln!("E: Failed to read manifest. cause: {}", err);
+        exit(exit_code::of_io(&err));
+    });
+
+    let (id, plan) = {
+        let build = match id {
+            Some(id) => manifest.builds.iter().find(|b| b.id == id),
+            None => manifest.live().last().copied(),
+        };
+        let build = match build {
+            Some(it) => it,
+            None => {
+                eprintln!(
+                    "E: {} has no record of such a build.",
+                    Manifest::path(workspace).display()
+                );
+                exit(exit_code::NOT_FOUND);
+            }
+        };
+        if let Some(by) = manifest
+            .builds
+            .iter()
+            .find(|b| b.upgrade_of == Some(build.id))
+        {
+            eprintln!(
+                "E: Build {} was upgraded by build {}, it can't be undone.",
+                build.id, by.id
+            );
+            exit(exit_code::FAILURE);
+        }
+        if build.upgrade_of.is_some() {
+            eprintln!(
+                "E: Build {} is an upgrade, upgrades can't be undone.",
+                build.id
+            );
+            exit(exit_code::FAILURE);
+        }
+
+        match undo::plan_undo(&manifest, build, workspace) {
+            Ok(plan) => (build.id, plan),
+            Err(err) => {
+                eprintln!("E: Failed to read workspace. cause: {}", err);
+                exit(exit_code::of_io(&err));
+            }
+        }
+    };
+
+    if !plan.modified.is_empty() && !force {
+        eprintln!("Files changed since build {}:", id);
+        plan.modified.iter().for_each(|p| eprintln!("\t{}", p));
+        eprintln!("E: Stop undoing the build.(use -f to remove them anyway).");
+        exit(exit_code::FAILURE);
+    }
+
+    let mut tx = Transaction::begin(workspace).unwrap_or_else(|err| {
+        eprintln!("E: Failed to prepare {}: {}", workspace.display(), err);
+        exit(exit_code::of_io(&err));
+    });
+    manifest.builds.retain(|b| b.id != id);
+    let kept_dirs = match apply_undo(&mut tx, &plan, &manifest, id, workspace) {
+        Ok(it) => it,
+        Err(err) => {
+            eprintln!("E: Failed to undo build {}: {}", id, err);
+            rollback(tx);
+            exit(exit_code::of_io(&err));
+        }
+    };
+    if let Err(err) = tx.finish() {
+        eprintln!("W: Failed to clean up staging dir: {}", err);
+    }
+
+    for path in &plan.remove {
+        if !plan.missing.contains(path) {
+            println!("Removed file: {}", workspace.join(path).display());
+        }
+    }
+    for (backup, path) in &plan.restore {
+        println!(
+            "Restored: {} -> {}",
+            workspace.join(backup).display(),
+            workspace.join(path).display()
+        );
+    }
+    for dir in plan.dirs.iter().filter(|d| !kept_dirs.contains(d)) {
+        println!("Removed dir: {}", workspace.join(dir).display());
+    }
+    for path in &plan.overwritten {
+        println!(
+            "W: Kept {}, it was overwritten without a backup.",
+            workspace.join(path).display()
+        );
+    }
+    for (path, by) in &plan.shared {
+        println!(
+            "W: Kept {}, build {} wrote it too.",
+            workspace.join(path).display(),
+            by
+        );
+    }
+    for dir in &kept_dirs {
+        println!(
+            "W: Kept {}, it is not empty.",
+            workspace.join(dir).display()
+        );
+    }
+    println!("Undid build {}.", id);
+}
+
+/// Returns the dirs the build created that are kept because they are not
+/// empty.
+fn apply_undo(
+    tx: &mut Transaction,
+    plan: &UndoPlan,
+    manifest: &Manifest,
+    id: u32,
+    workspace: &Path,
+) -> Result<Vec<String>, io::Error> {
+    for path in plan.remove.iter().filter(|p| !plan.missing.contains(p)) {
+        tx.remove_file(Path::new(path))?;
+    }
+    for (backup, path) in &plan.restore {
+        if !plan.missing.contains(path) {
+            tx.remove_file(Path::new(path))?;
+        }
+        tx.rename(Path::new(backup), Path::new(path))?;
+    }
+
+    let mut kept = vec![];
+    for dir in &plan.dirs {
+        if undo::is_empty_dir(&workspace.join(dir)) {
+            tx.remove_dir(Path::new(dir))?;
+        } else if workspace.join(dir).is_dir() {
+            kept.push(dir.clone());
+        }
+    }
+
+    let base = Manifest::base_path(Path::new(""), id);
+    if workspace.join(&base).is_file() {
+        tx.remove_file(&base)?;
+    }
+    let manifest_path = Manifest::path(Path::new(""));
+    if manifest.builds.is_empty() {
+        tx.remove_file(&manifest_path)?;
+        for dir in [base.parent(), manifest_path.parent()]
+            .into_iter()
+            .flatten()
+        {
+            if undo::is_empty_dir(&workspace.join(dir)) {
+                tx.remove_dir(dir)?;
+            }
+        }
+    } else {
+        let s = manifest
+            .to_toml()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        tx.write_file(&manifest_path, s.as_bytes())?;
+    }
+    Ok(kept)
+}
+
+//------------------------------------------------------------------------------
+//   Undo:
+//------------------------------------------------------------------------------
+
 //==============================================================================
 //   :profiles
 //==============================================================================
 
-fn show_profile_content(args: Args) {
-    let dir_root = {
+fn show_profile_content(settings: &Settings, args: Args) {
+    let limits = profile_limits(settings, &args);
+    let profile = {
         let profile_name = args.profile.unwrap_or_else(|| {
-            println!("E: Not give profile name arg.");
-            exit(1);
+            eprintln!("E: Not give profile name arg.");
+            exit(exit_code::USAGE);
         });
-        let profile_content = get_profile_path(profile_name);
-        DirRoot::read_from(&profile_content).unwrap_or_else(|err| {
+        let profile_content = profile_path(settings, &profile_name);
+        Profile::read_with_limits(&profile_content, &limits).unwrap_or_else(|err| {
             eprintln!("E: Failed to read profile. cause: {}", err);
-            exit(1);
+            exit(err.exit_code());
         })
     };
 
-    dir_root.info();
+    profile.info();
+}
+
+/// Limits for reading profiles, the defaults unless the settings or the
+/// command line change them.
+fn profile_limits(settings: &Settings, args: &Args) -> Limits {
+    let set = settings.wsinit.limits;
+    let default = Limits::default();
+    Limits {
+        max_size: set.max_size.unwrap_or(default.max_size),
+        max_entries: set.max_entries.unwrap_or(default.max_entries),
+        max_depth: set.max_depth.unwrap_or(default.max_depth),
+        allow_external_links: args.allow_external_links,
+    }
 }
 
 fn get_default_profile(settings: &Settings) -> String {
     settings.wsinit.get_default()
 }
 
-fn get_profiles() -> Result<Vec<String>, std::io::Error> {
-    let conf_dir = get_os_config_dir() + PROFILES_DIR_NAME;
-    Ok(fs::read_dir(conf_dir)?
-        .map(|e| {
-            let f = e.unwrap();
-            f.file_name().to_str().unwrap().to_string()
-        })
-        .collect())
+/// Profiles of every root, with a warning for roots that can't be read.
+fn get_profiles(roots: &Roots) -> Vec<Listed<'_>> {
+    let (listed, failed) = roots.list();
+    for (root, err) in failed {
+        eprintln!(
+            "W: Failed to read profile root {} ({}): {}",
+            root.name,
+            root.path.display(),
+            err
+        );
+    }
+    listed
+}
+
+/// Name of a listed profile, with the root it is in.
+fn profile_label(profile: &Listed) -> String {
+    let mut notes = vec![profile.root.name.clone()];
+    if profile.root.read_only {
+        notes.push("read-only".to_string());
+    }
+    if let Some(by) = profile.shadowed_by {
+        notes.push(format!("shadowed by {}", by));
+    }
+    format!("{} ({})", profile.name, notes.join(", "))
 }
 
-fn print_all_profiles() {
-    print_profiles_lst(&get_profiles().unwrap_or(vec![]))
+fn print_all_profiles(settings: &Settings) {
+    let roots = profile_roots(settings);
+    let labels: Vec<String> = get_profiles(&roots).iter().map(profile_label).collect();
+    print_profiles_lst(&labels)
 }
 
 //------------------------------------------------------------------------------
 //   profiles:
 //------------------------------------------------------------------------------
 
+//==============================================================================
+//   :Template
+//==============================================================================
+
+/// Variables every profile can use without declaring them.
+fn builtin_vars(profile_name: &str, target: &str) -> Vars {
+    let mut vars = Vars::new();
+    vars.insert("project_name".to_string(), project_name(target));
+    vars.insert(
+        "profile_name".to_string(),
+        profile_name.trim_end_matches(".bincode").to_string(),
+    );
+    vars
+}
+
+/// Name of the directory the workspace is built into.
+fn project_name(target: &str) -> String {
+    let path = Path::new(target);
+    fs::canonicalize(path)
+        .unwrap_or(path.to_path_buf())
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Work out a value for every declared parameter.
+///
+/// `--set` wins over `--answers`, which wins over `recorded` values from an
+/// earlier build, which win over asking the user. Without a terminal to ask
+/// on, the declared default is used or the build fails. Values given for
+/// undeclared names still fill their placeholders.
+fn resolve_vars(meta: &ProfileMeta, args: &Args, mut vars: Vars, recorded: Vars) -> Vars {
+    let mut given = recorded;
+    if let Some(path) = &args.answers {
+        given.extend(read_answers(path));
+    }
+    for kv in &args.set {
+        let (key, value) = kv.split_once('=').unwrap_or_else(|| {
+            eprintln!("E: Expected KEY=VALUE for --set, got \"{}\".", kv);
+            exit(exit_code::USAGE);
+        });
+        given.insert(key.trim().to_string(), value.to_string());
+    }
+
+    let interactive = io::stdin().is_terminal();
+    for param in &meta.params {
+        let default = param
+            .default
+            .clone()
+            .or_else(|| vars.get(&param.name).cloned());
+
+        let value = match given.remove(&param.name) {
+            Some(value) => param.validate(&value).unwrap_or_else(|err| {
+                eprintln!("E: Invalid value for {}: {}", param.name, err);
+                exit(exit_code::USAGE);
+            }),
+            None if interactive => prompt_param(param, default),
+            None => default.unwrap_or_else(|| {
+                eprintln!(
+                    "E: No value for parameter {}, give it with --set or --answers.",
+                    param.name
+                );
+                exit(exit_code::USAGE);
+            }),
+        };
+        vars.insert(param.name.clone(), value);
+    }
+
+    vars.extend(given);
+    vars
+}
+
+fn read_answers(path: &str) -> Vars {
+    let table: toml::Table = fs::read_to_string(path)
+        .and_then(|s| {
+            toml::from_str(&s).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("E: Failed to read answers file {}. cause: {}", path, err);
+            exit(exit_code::of_io(&err));
+        });
+
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                toml::Value::String(s) => s,
+                toml::Value::Boolean(b) => b.to_string(),
+                toml::Value::Integer(i) => i.to_string(),
+                other => {
+                    eprintln!(
+                        "E: Answer for {} must be a string, bool or integer, found {}.",
+                        key,
+                        other.type_str()
+                    );
+                    exit(exit_code::INVALID);
+                }
+            };
+            (key, value)
+        })
+        .collect()
+}
+
+fn prompt_param(param: &Param, default: Option<String>) -> String {
+    loop {
+        print!("{}", param.name);
+        if let Some(help) = &param.help {
+            print!(" ({})", help);
+        }
+        if !param.choices.is_empty() {
+            print!(" [{}]", param.choices.join("/"));
+        }
+        if let Some(default) = &default {
+            print!(" (default: {})", default);
+        }
+        print!(": ");
+        let _ = io::stdout().flush();
+
+        let buf = read_answer();
+        let answer = match (buf.trim(), &default) {
+            ("", Some(default)) => default.clone(),
+            (answer, _) => answer.to_string(),
+        };
+
+        match param.validate(&answer) {
+            Ok(value) => return value,
+            Err(err) => eprintln!("E: {}", err),
+        }
+    }
+}
+
+/// Read one answer from stdin. Ends the process once there is nothing more
+/// to read, the user pressed Ctrl-D or stdin was closed, like quitting.
+fn read_answer() -> String {
+    let mut buf = String::new();
+    match io::stdin().read_line(&mut buf) {
+        Ok(0) => {
+            println!();
+            println!("Give up!");
+            exit(exit_code::FAILURE);
+        }
+        Ok(_) => buf,
+        Err(err) => {
+            eprintln!("E: Failed to read an answer: {}", err);
+            exit(exit_code::FAILURE);
+        }
+    }
+}
+
+/// Paths of the bootstrap files in the rendered profile.
+fn bootstrap_files(meta: &ProfileMeta, vars: &Vars) -> Vec<String> {
+    meta.bootstrap
+        .iter()
+        .map(|path| template::render(path.trim_start_matches("./"), vars))
+        .collect()
+}
+
+fn render_profile(dir_root: DirRoot, vars: &Vars, limits: &Limits) -> DirRoot {
+    let unresolved: Vec<String> = dir_root
+        .placeholders()
+        .into_iter()
+        .filter(|name| !vars.contains_key(name))
+        .collect();
+    if !unresolved.is_empty() {
+        eprintln!(
+            "W: No value for template variables, left as is: {}",
+            unresolved.join(", ")
+        );
+    }
+    let dir_root = dir_root.render(vars);
+    if let Err(err) = dir_root.check_names() {
+        eprintln!("E: Template variables make a name unusable: {}", err);
+        exit(err.exit_code());
+    }
+    if !limits.allow_external_links {
+        if let Err(err) = dir_root.check_links() {
+            eprintln!("E: Template variables make a link unsafe: {}", err);
+            exit(err.exit_code());
+        }
+    }
+    dir_root
+}
+
+//------------------------------------------------------------------------------
+//   Template:
+//------------------------------------------------------------------------------
+
 //==============================================================================
 //   :Settings
 //==============================================================================
@@ -318,15 +1345,14 @@ fn set_default(mut settings: Settings, args: Args) {
         profile
     } else {
         let mut buf = String::new();
-        let profiles = match get_profiles() {
-            Ok(it) => it,
-            Err(err) => {
-                eprintln!("E: Error in get all profiles. cause: {}", err);
-                exit(1)
-            }
-        };
+        let roots = profile_roots(&settings);
+        let profiles: Vec<Listed> = get_profiles(&roots)
+            .into_iter()
+            .filter(|p| p.shadowed_by.is_none())
+            .collect();
 
-        print_profiles_lst(&profiles);
+        let labels: Vec<String> = profiles.iter().map(profile_label).collect();
+        print_profiles_lst(&labels);
         println!("Which one: (0 for cancle)");
 
         std::io::stdin().read_line(&mut buf).unwrap();
@@ -334,7 +1360,7 @@ fn set_default(mut settings: Settings, args: Args) {
             Ok(it) => it,
             Err(_) => {
                 eprintln!("Unable get a valid number.");
-                exit(1);
+                exit(exit_code::USAGE);
             }
         };
         if num == 0 {
@@ -346,20 +1372,21 @@ fn set_default(mut settings: Settings, args: Args) {
             .get(num - 1)
             .unwrap_or_else(|| {
                 eprintln!("Please input a valid number.");
-                exit(1);
+                exit(exit_code::USAGE);
             })
+            .name
             .clone()
     };
 
     settings.wsinit.set_default(&profile);
-    match settings.write(&(get_os_config_dir() + SETTING_NAME)) {
+    match settings.write(config_dir().join(SETTING_NAME)) {
         Ok(_) => {
             println!("Success.");
             exit(0);
         }
         Err(err) => {
             eprintln!("E: Failed write settings. cause: {}", err);
-            exit(1);
+            exit(err.exit_code());
         }
     };
 }
@@ -367,3 +1394,64 @@ fn set_default(mut settings: Settings, args: Args) {
 //------------------------------------------------------------------------------
 //   Settings:
 //------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use profile::{FileContent, FileInfo, FileType};
+
+    fn text(name: &str) -> FileInfo {
+        FileInfo::new(name, FileType::Text, FileContent::Text("x".to_string()))
+    }
+
+    #[test]
+    fn strip_bootstrap_keeps_empty_profile_dirs() {
+        let target = tempfile::tempdir().unwrap();
+        let mut root = DirRoot::new("");
+        let mut setup = DirRoot::new("setup");
+        setup.files.push(text("bootstrap.sh"));
+        root.dirs.push(setup);
+        root.dirs.push(DirRoot::new("logs"));
+        let mut data = DirRoot::new("data");
+        data.dirs.push(DirRoot::new("raw"));
+        data.files.push(text("seed.sh"));
+        root.dirs.push(data);
+
+        let steps = plan(&root, target.path());
+        let mut tx = Transaction::begin(target.path()).unwrap();
+        let built = tx.apply(&steps).unwrap();
+        let bootstrap = ["setup/bootstrap.sh".to_string(), "data/seed.sh".to_string()];
+        let (files, dirs) = strip_bootstrap(&mut tx, &steps, &built, &bootstrap).unwrap();
+        tx.finish().unwrap();
+
+        assert_eq!(files, bootstrap);
+        assert_eq!(dirs, ["setup"]);
+        assert!(target.path().join("logs").is_dir());
+        assert!(target.path().join("data/raw").is_dir());
+        assert!(!target.path().join("setup").exists());
+    }
+
+    #[test]
+    fn failed_hooks_keep_or_roll_back_the_build() {
+        for (on_failure, kept) in [(OnFailure::Abort, true), (OnFailure::Rollback, false)] {
+            let target = tempfile::tempdir().unwrap();
+            let mut tx = Transaction::begin(target.path()).unwrap();
+            tx.write_file(Path::new("README"), b"readme").unwrap();
+
+            let root = DirRoot::new("");
+            let hooks =
+                ProfileMeta::from_toml("[[hooks]]\nstage = \"post-build\"\nrun = \"exit 4\"\n")
+                    .unwrap()
+                    .hooks
+                    .into_iter()
+                    .map(|h| Hook { on_failure, ..h })
+                    .collect::<Vec<_>>();
+            let runner = Runner::new(&hooks, &root, target.path(), &Vars::new(), vec![]);
+            let failure = runner.run_stage(Stage::PostBuild).err().unwrap();
+
+            settle(tx, failure.on_failure);
+            assert_eq!(target.path().join("README").exists(), kept);
+            assert_eq!(fs::read_dir(target.path()).unwrap().count(), kept as usize);
+        }
+    }
+}
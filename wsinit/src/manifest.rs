@@ -0,0 +1,369 @@
+//! Record of the builds done into a workspace, kept in `.wsinit/manifest`.
+//!
+//! ```toml
+//! [[builds]]
+//! id = 1
+//! profile = "rust"
+//! profile_hash = "9f86d0..."
+//! timestamp = 1760000000
+//! dirs = ["src"]
+//!
+//! [builds.params]
+//! project_name = "demo"
+//!
+//! [[builds.files]]
+//! path = "src/main.rs"
+//! hash = "2c26b4..."
+//! how = "created"
+//! ```
+//!
+//! The manifest may come with a cloned repo, so every path in it is checked
+//! to be a plain path inside the workspace before it is used.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use profile::name;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::plan::{Action, Entry, State, Step};
+
+/// Dir inside the workspace where wsinit keeps its records.
+pub const WSINIT_DIR: &str = ".wsinit";
+const MANIFEST_NAME: &str = "manifest";
+const BASE_DIR_NAME: &str = "base";
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub builds: Vec<Build>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Build {
+    pub id: u32,
+    pub profile: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile_version: Option<String>,
+    /// sha256 of the profile file.
+    pub profile_hash: String,
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    /// The build this one upgraded to a newer profile, and replaces.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upgrade_of: Option<u32>,
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    /// Dirs this build created, relative to the workspace.
+    #[serde(default)]
+    pub dirs: Vec<String>,
+    #[serde(default)]
+    pub files: Vec<FileRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FileRecord {
+    /// Where the profile puts the file, relative to the workspace.
+    pub path: String,
+    /// sha256 of the content the profile has for it.
+    pub hash: String,
+    pub how: How,
+    /// Where the file that was there before went, for `backup`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<String>,
+    /// Where the new file was written instead, for `rename`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub written_to: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum How {
+    /// Did not exist before.
+    Created,
+    /// Replaced an existing file, see `backup`.
+    Overwritten,
+    /// An existing file already had the same content.
+    Unchanged,
+    /// An existing file was kept.
+    Skipped,
+    /// An existing file was kept and the new one written to `written_to`.
+    Renamed,
+    /// Local changes and the profile's were merged by an upgrade.
+    Merged,
+    /// An upgrade could not merge the changes, see `written_to` or the
+    /// conflict markers in the file.
+    Conflicted,
+}
+
+impl Manifest {
+    pub fn path(workspace: &Path) -> PathBuf {
+        workspace.join(WSINIT_DIR).join(MANIFEST_NAME)
+    }
+
+    /// The rendered profile build `id` wrote, kept as the base of upgrades.
+    pub fn base_path(workspace: &Path, id: u32) -> PathBuf {
+        workspace
+            .join(WSINIT_DIR)
+            .join(BASE_DIR_NAME)
+            .join(format!("{}.bincode", id))
+    }
+
+    /// Read the manifest of `workspace`, or an empty one if it has none. A
+    /// manifest that does not parse, or records unsafe paths, is
+    /// `InvalidData`.
+    pub fn read_from(workspace: &Path) -> Result<Self, io::Error> {
+        let s = match fs::read_to_string(Self::path(workspace)) {
+            Ok(it) => it,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+        let invalid = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+        let manifest: Self = toml::from_str(&s).map_err(|err| invalid(err.to_string()))?;
+        manifest.check_paths().map_err(invalid)?;
+        Ok(manifest)
+    }
+
+    /// Refuse paths that would lead out of the workspace, or into the
+    /// records wsinit keeps in it.
+    fn check_paths(&self) -> Result<(), String> {
+        for build in &self.builds {
+            let files = build.files.iter().flat_map(|f| {
+                [Some(&f.path), f.backup.as_ref(), f.written_to.as_ref()]
+                    .into_iter()
+                    .flatten()
+            });
+            for path in build.dirs.iter().chain(files) {
+                if let Err(err) = name::check_path(path) {
+                    return Err(format!(
+                        "build {} records an unsafe path {:?}: {}",
+                        build.id, path, err
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String, String> {
+        match toml::to_string(self) {
+            Ok(it) => Ok(it),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    pub fn next_id(&self) -> u32 {
+        self.builds.iter().map(|b| b.id).max().unwrap_or(0) + 1
+    }
+
+    /// Builds that no later upgrade replaced, oldest first.
+    pub fn live(&self) -> Vec<&Build> {
+        let replaced: Vec<u32> = self.builds.iter().filter_map(|b| b.upgrade_of).collect();
+        self.builds
+            .iter()
+            .filter(|b| !replaced.contains(&b.id))
+            .collect()
+    }
+}
+
+impl Build {
+    /// Record of building `steps`, after their actions were decided.
+    pub fn new(steps: &[Step]) -> Self {
+        let mut build = Self::empty();
+
+        for step in steps {
+            let f = match step.entry {
+                Entry::Dir => {
+                    if step.action == Action::Write {
+                        build.dirs.push(to_slash(&step.path));
+                    }
+                    continue;
+                }
+                Entry::File(f) => f,
+            };
+
+            let (how, backup, written_to) = match (&step.action, step.state) {
+                (Action::Keep, State::Same) => (How::Unchanged, None, None),
+                (Action::Keep, _) => (How::Skipped, None, None),
+                (Action::Write, State::New) => (How::Created, None, None),
+                (Action::Write, _) => (How::Overwritten, None, None),
+                (Action::Backup(p), _) => (How::Overwritten, Some(to_slash(p)), None),
+                (Action::WriteAs(p), _) => (How::Renamed, None, Some(to_slash(p))),
+            };
+            build.files.push(FileRecord {
+                path: to_slash(&step.path),
+                hash: hash(f.as_bytes()),
+                how,
+                backup,
+                written_to,
+            });
+        }
+        build
+    }
+
+    /// Record of a build that has not written anything yet. Where it comes
+    /// from and its id are filled in when it is recorded.
+    pub fn empty() -> Self {
+        Self {
+            id: 0,
+            profile: String::new(),
+            profile_version: None,
+            profile_hash: String::new(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            upgrade_of: None,
+            params: BTreeMap::new(),
+            dirs: vec![],
+            files: vec![],
+        }
+    }
+}
+
+pub fn hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Paths are stored with `/` whatever the platform.
+pub fn to_slash(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use profile::{FileContent, FileInfo, FileType};
+
+    fn read(toml: &str) -> Result<Manifest, io::Error> {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(WSINIT_DIR)).unwrap();
+        fs::write(Manifest::path(dir.path()), toml).unwrap();
+        Manifest::read_from(dir.path())
+    }
+
+    const BUILD: &str = "[[builds]]\nid = 1\nprofile = \"p\"\nprofile_hash = \"\"\ntimestamp = 0\n";
+
+    #[test]
+    fn round_trip() {
+        let mut build = Build::empty();
+        build.id = 1;
+        build.profile = "rust".to_string();
+        build.dirs.push("src".to_string());
+        build.files.push(FileRecord {
+            path: "src/main.rs".to_string(),
+            hash: hash(b"fn main() {}"),
+            how: How::Overwritten,
+            backup: Some("src/main.rs.orig".to_string()),
+            written_to: None,
+        });
+        let manifest = Manifest {
+            builds: vec![build],
+        };
+
+        let manifest = read(&manifest.to_toml().unwrap()).unwrap();
+        let build = &manifest.builds[0];
+        assert_eq!(build.profile, "rust");
+        assert_eq!(build.dirs, ["src"]);
+        assert_eq!(build.files[0].backup.as_deref(), Some("src/main.rs.orig"));
+        assert!(build.files[0].how == How::Overwritten);
+        assert_eq!(manifest.next_id(), 2);
+    }
+
+    #[test]
+    fn records_what_each_step_did() {
+        let f = FileInfo::new("f", FileType::Text, FileContent::Text("x".to_string()));
+        let step = |path: &str, entry, state, action| Step {
+            path: PathBuf::from(path),
+            entry,
+            state,
+            action,
+        };
+        let steps = [
+            step("src", Entry::Dir, State::New, Action::Write),
+            step("docs", Entry::Dir, State::Exists, Action::Keep),
+            step("src/a", Entry::File(&f), State::New, Action::Write),
+            step("b", Entry::File(&f), State::Same, Action::Keep),
+            step("c", Entry::File(&f), State::Overwrite, Action::Keep),
+            step("d", Entry::File(&f), State::Overwrite, Action::Write),
+            step(
+                "e",
+                Entry::File(&f),
+                State::Overwrite,
+                Action::Backup(PathBuf::from("e.orig")),
+            ),
+            step(
+                "g",
+                Entry::File(&f),
+                State::Overwrite,
+                Action::WriteAs(PathBuf::from("g.new")),
+            ),
+        ];
+
+        let build = Build::new(&steps);
+        assert_eq!(build.dirs, ["src"]);
+        let records: Vec<(&str, How, Option<&str>, Option<&str>)> = build
+            .files
+            .iter()
+            .map(|f| {
+                (
+                    f.path.as_str(),
+                    f.how,
+                    f.backup.as_deref(),
+                    f.written_to.as_deref(),
+                )
+            })
+            .collect();
+        assert!(
+            records
+                == [
+                    ("src/a", How::Created, None, None),
+                    ("b", How::Unchanged, None, None),
+                    ("c", How::Skipped, None, None),
+                    ("d", How::Overwritten, None, None),
+                    ("e", How::Overwritten, Some("e.orig"), None),
+                    ("g", How::Renamed, None, Some("g.new")),
+                ]
+        );
+        assert!(build.files.iter().all(|r| r.hash == hash(b"x")));
+    }
+
+    #[test]
+    fn upgrades_replace_the_build_they_upgraded() {
+        let build = |id, upgrade_of| {
+            let mut build = Build::empty();
+            build.id = id;
+            build.upgrade_of = upgrade_of;
+            build
+        };
+        let manifest = Manifest {
+            builds: vec![build(1, None), build(2, None), build(3, Some(1))],
+        };
+        let live: Vec<u32> = manifest.live().iter().map(|b| b.id).collect();
+        assert_eq!(live, [2, 3]);
+        assert_eq!(manifest.next_id(), 4);
+    }
+
+    #[test]
+    fn refuses_paths_outside_of_the_workspace() {
+        let file = |path: &str, backup: &str| {
+            format!(
+                "{}[[builds.files]]\npath = {:?}\nhash = \"\"\nhow = \"overwritten\"\nbackup = {:?}\n",
+                BUILD, path, backup
+            )
+        };
+        assert!(read(&file("a", "a.orig")).is_ok());
+        assert!(read(&file("../../.ssh/authorized_keys", "evil")).is_err());
+        assert!(read(&file("a", "/home/u/.bashrc")).is_err());
+        assert!(read(&file("a", ".wsinit/manifest")).is_err());
+        assert!(read(&format!("{}dirs = [\"src/../..\"]\n", BUILD)).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! Line based three-way merge of text files.
+
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+
+pub struct Merged {
+    pub text: String,
+    /// Number of places marked with conflict markers in `text`.
+    pub conflicts: usize,
+}
+
+/// Lines `base[start..end]` replaced with `lines` by one side.
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: &'a [&'a str],
+}
+
+fn hunks<'a>(base: &[&str], side: &'a [&'a str]) -> Vec<Hunk<'a>> {
+    capture_diff_slices(Algorithm::Myers, base, side)
+        .into_iter()
+        .filter(|op| !matches!(op, DiffOp::Equal { .. }))
+        .map(|op| {
+            let (old, new) = (op.old_range(), op.new_range());
+            Hunk {
+                start: old.start,
+                end: old.end,
+                lines: &side[new],
+            }
+        })
+        .collect()
+}
+
+/// `base[start..end]` with the `hunks` of one side, which all lie inside
+/// that range, applied.
+fn apply(base: &[&str], start: usize, end: usize, hunks: &[&Hunk]) -> Vec<String> {
+    let mut out = vec![];
+    let mut at = start;
+    for h in hunks {
+        out.extend(base[at..h.start].iter().map(|s| s.to_string()));
+        out.extend(h.lines.iter().map(|s| s.to_string()));
+        at = h.end;
+    }
+    out.extend(base[at..end].iter().map(|s| s.to_string()));
+    out
+}
+
+/// Merge the changes from `base` to `ours` and from `base` to `theirs`.
+///
+/// Changes that touch the same or adjacent lines of `base` conflict, unless
+/// both sides made the same change, and are kept with both versions between
+/// `<<<<<<<`, `=======` and `>>>>>>>` markers.
+pub fn merge3(base: &str, ours: &str, theirs: &str) -> Merged {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let ours_hunks = hunks(&base, &ours);
+    let theirs_hunks = hunks(&base, &theirs);
+
+    let mut merged = Merged {
+        text: String::new(),
+        conflicts: 0,
+    };
+    let (mut i, mut j, mut at) = (0, 0, 0);
+    while i < ours_hunks.len() || j < theirs_hunks.len() {
+        // Start a cluster with the hunk that comes first, then pull in every
+        // hunk of either side that overlaps or touches it.
+        let first = match (ours_hunks.get(i), theirs_hunks.get(j)) {
+            (Some(o), Some(t)) => o.start.min(t.start),
+            (Some(o), None) => o.start,
+            (None, Some(t)) => t.start,
+            (None, None) => unreachable!(),
+        };
+        let (mut end, mut mine, mut yours) = (first, vec![], vec![]);
+        loop {
+            if let Some(h) = ours_hunks.get(i).filter(|h| h.start <= end) {
+                end = end.max(h.end);
+                mine.push(h);
+                i += 1;
+            } else if let Some(h) = theirs_hunks.get(j).filter(|h| h.start <= end) {
+                end = end.max(h.end);
+                yours.push(h);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        merged.text.extend(base[at..first].iter().copied());
+        let a = apply(&base, first, end, &mine);
+        let b = apply(&base, first, end, &yours);
+        if yours.is_empty() || a == b {
+            merged.text.extend(a);
+        } else if mine.is_empty() {
+            merged.text.extend(b);
+        } else {
+            merged.conflicts += 1;
+            merged.text.push_str("<<<<<<< ours\n");
+            push_lines(&mut merged.text, &a);
+            merged.text.push_str("=======\n");
+            push_lines(&mut merged.text, &b);
+            merged.text.push_str(">>>>>>> theirs\n");
+        }
+        at = end;
+    }
+    merged.text.extend(base[at..].iter().copied());
+    merged
+}
+
+/// Append `lines`, ending with a line break so a marker can follow.
+fn push_lines(text: &mut String, lines: &[String]) {
+    text.extend(lines.iter().map(String::as_str));
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_changes_from_both_sides() {
+        let base = "a\nb\nc\nd\ne\n";
+        let ours = "a\nB\nc\nd\ne\n";
+        let theirs = "a\nb\nc\nd\nE\nf\n";
+        let merged = merge3(base, ours, theirs);
+        assert_eq!(merged.conflicts, 0);
+        assert_eq!(merged.text, "a\nB\nc\nd\nE\nf\n");
+    }
+
+    #[test]
+    fn same_change_on_both_sides_is_not_a_conflict() {
+        let merged = merge3("a\nb\n", "a\nc\n", "a\nc\n");
+        assert_eq!(merged.conflicts, 0);
+        assert_eq!(merged.text, "a\nc\n");
+    }
+
+    #[test]
+    fn marks_conflicting_changes() {
+        let merged = merge3("a\nb\nc\n", "a\nours\nc\n", "a\ntheirs\nc");
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            merged.text,
+            "a\n<<<<<<< ours\nours\nc\n=======\ntheirs\nc\n>>>>>>> theirs\n"
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Work out what building a profile into a target dir would do, before
+//! anything is written.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use profile::{DirRoot, FileInfo};
+
+pub enum Entry<'a> {
+    Dir,
+    File(&'a FileInfo),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Nothing there yet.
+    New,
+    /// A dir the profile also has, its contents are merged.
+    Exists,
+    /// A file with the same content as the profile's.
+    Same,
+    /// A file the profile would overwrite.
+    Overwrite,
+    /// A file where the profile has a dir, or the other way around.
+    Blocked,
+}
+
+/// What the builder does for a step.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Create the dir or write the file.
+    Write,
+    /// Leave what is there alone.
+    Keep,
+    /// Move the existing file to this path, then write.
+    Backup(PathBuf),
+    /// Write the file to this path instead, relative to the target dir.
+    WriteAs(PathBuf),
+}
+
+pub struct Step<'a> {
+    /// Relative to the target dir.
+    pub path: PathBuf,
+    pub entry: Entry<'a>,
+    pub state: State,
+    pub action: Action,
+}
+
+/// Every dir and file of `root`, parents before children, with what is
+/// already at its place under `target`.
+pub fn plan<'a>(root: &'a DirRoot, target: &Path) -> Vec<Step<'a>> {
+    fn walk<'a>(
+        root: &'a DirRoot,
+        target: &Path,
+        prefix: &Path,
+        parent_new: bool,
+        steps: &mut Vec<Step<'a>>,
+    ) {
+        for d in &root.dirs {
+            let path = prefix.join(&d.name);
+            let state = match parent_new {
+                true => State::New,
+                false => match fs::metadata(target.join(&path)) {
+                    Err(_) => State::New,
+                    Ok(meta) if meta.is_dir() => State::Exists,
+                    Ok(_) => State::Blocked,
+                },
+            };
+            steps.push(Step {
+                path: path.clone(),
+                entry: Entry::Dir,
+                state,
+                action: match state {
+                    State::New => Action::Write,
+                    _ => Action::Keep,
+                },
+            });
+            if state != State::Blocked {
+                walk(d, target, &path, state == State::New, steps);
+            }
+        }
+
+        for f in &root.files {
+            let path = prefix.join(&f.name);
+            let state = match parent_new {
+                true => State::New,
+                false => match fs::symlink_metadata(target.join(&path)) {
+                    Err(_) => State::New,
+                    Ok(meta) if meta.is_dir() => State::Blocked,
+                    Ok(_) => match profile::read_entry(&target.join(&path)) {
+                        Ok(old) if old == f.as_bytes() => State::Same,
+                        _ => State::Overwrite,
+                    },
+                },
+            };
+            steps.push(Step {
+                path,
+                entry: Entry::File(f),
+                state,
+                action: match state {
+                    State::New | State::Overwrite => Action::Write,
+                    _ => Action::Keep,
+                },
+            });
+        }
+    }
+
+    let mut steps = vec![];
+    walk(root, target, Path::new(""), !target.is_dir(), &mut steps);
+    steps
+}
+
+/// Paths of the steps in `state`.
+pub fn paths_in(steps: &[Step], state: State) -> Vec<String> {
+    steps
+        .iter()
+        .filter(|s| s.state == state)
+        .map(|s| s.path.display().to_string())
+        .collect()
+}
+
+/// Every dir of `root`, parents before children, by its `/` separated path.
+pub fn dirs(root: &DirRoot) -> Vec<String> {
+    fn walk(root: &DirRoot, prefix: &str, dirs: &mut Vec<String>) {
+        for d in &root.dirs {
+            let path = format!("{}{}", prefix, d.name);
+            dirs.push(path.clone());
+            walk(d, &format!("{}/", path), dirs);
+        }
+    }
+
+    let mut dirs = vec![];
+    walk(root, "", &mut dirs);
+    dirs
+}
+
+/// Every file of `root` by its `/` separated path.
+pub fn files(root: &DirRoot) -> BTreeMap<String, &FileInfo> {
+    fn walk<'a>(root: &'a DirRoot, prefix: &str, files: &mut BTreeMap<String, &'a FileInfo>) {
+        for d in &root.dirs {
+            walk(d, &format!("{}{}/", prefix, d.name), files);
+        }
+        for f in &root.files {
+            files.insert(format!("{}{}", prefix, f.name), f);
+        }
+    }
+
+    let mut files = BTreeMap::new();
+    walk(root, "", &mut files);
+    files
+}
+
+/// Drop the files at `paths`, `/` separated, from `root`.
+pub fn remove_files(root: &mut DirRoot, paths: &[String]) {
+    fn walk(root: &mut DirRoot, prefix: &str, paths: &[String]) {
+        root.files
+            .retain(|f| !paths.contains(&format!("{}{}", prefix, f.name)));
+        for d in &mut root.dirs {
+            let prefix = format!("{}{}/", prefix, d.name);
+            walk(d, &prefix, paths);
+        }
+    }
+
+    walk(root, "", paths);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use profile::{FileContent, FileType};
+
+    fn text(name: &str, content: &str) -> FileInfo {
+        FileInfo::new(name, FileType::Text, FileContent::Text(content.to_string()))
+    }
+
+    fn states(steps: &[Step]) -> Vec<(String, &'static str)> {
+        steps
+            .iter()
+            .map(|s| {
+                let state = match s.state {
+                    State::New => "new",
+                    State::Exists => "exists",
+                    State::Same => "same",
+                    State::Overwrite => "overwrite",
+                    State::Blocked => "blocked",
+                };
+                (s.path.display().to_string(), state)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plans_against_what_is_there() {
+        let target = tempfile::tempdir().unwrap();
+        fs::create_dir_all(target.path().join("src/bin")).unwrap();
+        fs::write(target.path().join("src/main.rs"), "main").unwrap();
+        fs::write(target.path().join("src/lib.rs"), "old").unwrap();
+        fs::write(target.path().join("docs"), "a file").unwrap();
+        fs::create_dir(target.path().join("README")).unwrap();
+
+        let mut root = DirRoot::new("");
+        let mut src = DirRoot::new("src");
+        let mut bin = DirRoot::new("bin");
+        bin.files.push(text("cli.rs", "cli"));
+        src.dirs.push(bin);
+        src.files.push(text("main.rs", "main"));
+        src.files.push(text("lib.rs", "new"));
+        root.dirs.push(src);
+        let mut docs = DirRoot::new("docs");
+        docs.files.push(text("a.md", "a"));
+        root.dirs.push(docs);
+        let mut tests = DirRoot::new("tests");
+        tests.dirs.push(DirRoot::new("data"));
+        tests.files.push(text("it.rs", "it"));
+        root.dirs.push(tests);
+        root.files.push(text("README", "readme"));
+
+        let steps = plan(&root, target.path());
+        let expected = [
+            ("src", "exists"),
+            ("src/bin", "exists"),
+            ("src/bin/cli.rs", "new"),
+            ("src/main.rs", "same"),
+            ("src/lib.rs", "overwrite"),
+            ("docs", "blocked"),
+            ("tests", "new"),
+            ("tests/data", "new"),
+            ("tests/it.rs", "new"),
+            ("README", "blocked"),
+        ];
+        let expected: Vec<(String, &str)> =
+            expected.iter().map(|(p, s)| (p.to_string(), *s)).collect();
+        assert_eq!(states(&steps), expected);
+
+        let writes: Vec<String> = steps
+            .iter()
+            .filter(|s| s.action == Action::Write)
+            .map(|s| s.path.display().to_string())
+            .collect();
+        assert_eq!(
+            writes,
+            [
+                "src/bin/cli.rs",
+                "src/lib.rs",
+                "tests",
+                "tests/data",
+                "tests/it.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn everything_is_new_in_a_missing_target() {
+        let target = tempfile::tempdir().unwrap();
+        let mut root = DirRoot::new("");
+        let mut src = DirRoot::new("src");
+        src.files.push(text("main.rs", "main"));
+        root.dirs.push(src);
+
+        let steps = plan(&root, &target.path().join("new"));
+        assert!(steps.iter().all(|s| s.state == State::New));
+        assert_eq!(steps.len(), 2);
+    }
+}
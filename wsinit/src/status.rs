@@ -0,0 +1,170 @@
+//! Compare a workspace with what its builds wrote, as recorded in the
+//! manifest.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::Path,
+};
+
+use profile::{capture::CaptureOptions, DirRoot};
+
+use crate::{
+    manifest::{self, How, Manifest, WSINIT_DIR},
+    plan,
+    transaction::STAGING_PREFIX,
+};
+
+#[derive(Default)]
+pub struct Status {
+    pub unchanged: Vec<String>,
+    /// Content differs from what the build wrote.
+    pub modified: Vec<String>,
+    pub deleted: Vec<String>,
+    /// Not written by any build, and not ignored.
+    pub added: Vec<String>,
+}
+
+/// Files the builds recorded in `manifest` put into the workspace, with the
+/// hash of their content. A later build wins over an earlier one, and an
+/// upgrade replaces the build it upgraded.
+pub fn tracked(manifest: &Manifest) -> BTreeMap<String, String> {
+    let mut files = BTreeMap::new();
+    for build in manifest.live() {
+        for f in &build.files {
+            match f.how {
+                How::Skipped => {}
+                How::Renamed => {
+                    if let Some(path) = &f.written_to {
+                        files.insert(path.clone(), f.hash.clone());
+                    }
+                }
+                _ => {
+                    files.insert(f.path.clone(), f.hash.clone());
+                }
+            }
+        }
+    }
+    files
+}
+
+pub fn status(workspace: &Path, manifest: &Manifest) -> Result<Status, io::Error> {
+    let mut status = Status::default();
+    let tracked = tracked(manifest);
+
+    // Files that were there before a build and that it left alone or moved
+    // aside are not the user's additions either.
+    let mut known: BTreeSet<String> = tracked.keys().cloned().collect();
+    for build in &manifest.builds {
+        for f in &build.files {
+            known.insert(f.path.clone());
+            known.extend(f.backup.clone());
+        }
+    }
+
+    for (path, hash) in &tracked {
+        match profile::read_entry(&workspace.join(path)) {
+            Ok(bytes) if manifest::hash(&bytes) == *hash => status.unchanged.push(path.clone()),
+            Ok(_) => status.modified.push(path.clone()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => status.deleted.push(path.clone()),
+            Err(err) => return Err(err),
+        }
+    }
+
+    let opts = CaptureOptions {
+        keep_empty_files: true,
+        allow_external_links: true,
+        exclude: vec![WSINIT_DIR.to_string(), format!("{}*", STAGING_PREFIX)],
+        ..Default::default()
+    };
+    let root = DirRoot::capture(workspace.display().to_string(), &opts)?;
+    status.added = plan::files(&root)
+        .into_keys()
+        .filter(|path| !known.contains(path))
+        .collect();
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::manifest::{Build, FileRecord};
+
+    fn record(path: &str, content: &str, how: How) -> FileRecord {
+        FileRecord {
+            path: path.to_string(),
+            hash: manifest::hash(content.as_bytes()),
+            how,
+            backup: None,
+            written_to: None,
+        }
+    }
+
+    #[test]
+    fn classifies_the_files_of_a_workspace() {
+        let workspace = tempfile::tempdir().unwrap();
+        let ws = workspace.path();
+        fs::create_dir_all(ws.join("src")).unwrap();
+        fs::create_dir_all(ws.join(WSINIT_DIR)).unwrap();
+        fs::write(ws.join("src/main.rs"), "main").unwrap();
+        fs::write(ws.join("src/lib.rs"), "changed").unwrap();
+        fs::write(ws.join("kept"), "mine").unwrap();
+        fs::write(ws.join("kept.new"), "theirs").unwrap();
+        fs::write(ws.join("notes"), "added").unwrap();
+        fs::write(ws.join(WSINIT_DIR).join("manifest"), "").unwrap();
+
+        let mut build = Build::empty();
+        build.id = 1;
+        build.files = vec![
+            record("src/main.rs", "main", How::Created),
+            record("src/lib.rs", "lib", How::Created),
+            record("README", "readme", How::Created),
+            FileRecord {
+                written_to: Some("kept.new".to_string()),
+                ..record("kept", "theirs", How::Renamed)
+            },
+        ];
+        let manifest = Manifest {
+            builds: vec![build],
+        };
+
+        let status = status(ws, &manifest).unwrap();
+        assert_eq!(status.unchanged, ["kept.new", "src/main.rs"]);
+        assert_eq!(status.modified, ["src/lib.rs"]);
+        assert_eq!(status.deleted, ["README"]);
+        assert_eq!(status.added, ["notes"]);
+    }
+
+    #[test]
+    fn later_builds_win() {
+        let mut first = Build::empty();
+        first.id = 1;
+        first.files = vec![
+            record("a", "one", How::Created),
+            record("b", "one", How::Created),
+        ];
+        let mut second = Build::empty();
+        second.id = 2;
+        second.files = vec![
+            record("a", "two", How::Overwritten),
+            record("b", "other", How::Skipped),
+        ];
+        let mut manifest = Manifest {
+            builds: vec![first, second],
+        };
+
+        let tracked = tracked(&manifest);
+        assert_eq!(tracked["a"], manifest::hash(b"two"));
+        assert_eq!(tracked["b"], manifest::hash(b"one"));
+
+        // An upgrade of build 1 replaces it, `b` is no longer tracked.
+        let mut upgrade = Build::empty();
+        upgrade.id = 3;
+        upgrade.upgrade_of = Some(1);
+        manifest.builds.push(upgrade);
+        assert!(!super::tracked(&manifest).contains_key("b"));
+    }
+}
@@ -0,0 +1,299 @@
+//! Build a workspace all or nothing.
+//!
+//! Files are first written into a staging dir inside the target, where a
+//! full disk or a permission problem shows up before anything in the target
+//! has been touched. They are then moved into place one by one, and every
+//! change to the target is journaled so a failure, even after the commit,
+//! can be rolled back: moved in files are removed, replaced and backed up
+//! files are put back, and created dirs are removed again.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use profile::{FileContent, FileInfo, FileType};
+
+use crate::plan::{Action, Entry, Step};
+
+pub const STAGING_PREFIX: &str = ".wsinit-staging-";
+
+enum Undo {
+    RemoveDir(PathBuf),
+    CreateDir(PathBuf),
+    /// The staging dir, with whatever is still in it.
+    RemoveStaging(PathBuf),
+    RemoveFile(PathBuf),
+    /// Move a file back from `from` to `to`.
+    Move {
+        from: PathBuf,
+        to: PathBuf,
+    },
+}
+
+/// What a build changed, relative to the target dir.
+#[derive(Default)]
+pub struct Built {
+    pub dirs: Vec<PathBuf>,
+    pub files: Vec<PathBuf>,
+    /// Existing file and where it was backed up to.
+    pub backups: Vec<(PathBuf, PathBuf)>,
+}
+
+pub struct Transaction {
+    target: PathBuf,
+    staging: PathBuf,
+    journal: Vec<Undo>,
+}
+
+impl Transaction {
+    /// Create the target dir if needed, and the staging dir inside it.
+    pub fn begin(target: &Path) -> Result<Self, io::Error> {
+        let mut tx = Self {
+            target: target.to_path_buf(),
+            staging: target.join(format!("{}{}", STAGING_PREFIX, process::id())),
+            journal: vec![],
+        };
+
+        let result = tx.create_dirs(target).and_then(|_| {
+            fs::create_dir(&tx.staging)?;
+            tx.journal.push(Undo::RemoveStaging(tx.staging.clone()));
+            Ok(())
+        });
+        match result {
+            Ok(_) => Ok(tx),
+            Err(err) => {
+                let _ = tx.rollback();
+                Err(err)
+            }
+        }
+    }
+
+    /// Stage every file of `steps`, then move them into place.
+    pub fn apply(&mut self, steps: &[Step]) -> Result<Built, io::Error> {
+        let mut staged: Vec<PathBuf> = vec![];
+        for (i, step) in steps.iter().enumerate() {
+            if let (Entry::File(f), Action::Write | Action::Backup(_) | Action::WriteAs(_)) =
+                (&step.entry, &step.action)
+            {
+                let path = self.staging.join(i.to_string());
+                f.write(&path.to_string_lossy())?;
+                staged.push(path);
+            }
+        }
+
+        let mut built = Built::default();
+        let mut staged = staged.into_iter();
+        for step in steps {
+            let dest = self.target.join(&step.path);
+            match (&step.entry, &step.action) {
+                (_, Action::Keep) => {}
+                (Entry::Dir, _) => {
+                    self.create_dirs(&dest)?;
+                    built.dirs.push(step.path.clone());
+                }
+                (Entry::File(_), action) => {
+                    let path = match action {
+                        Action::Backup(backup) => {
+                            let to = self.target.join(backup);
+                            self.move_file(&dest, &to)?;
+                            built.backups.push((step.path.clone(), backup.clone()));
+                            step.path.clone()
+                        }
+                        Action::WriteAs(path) => path.clone(),
+                        _ => step.path.clone(),
+                    };
+                    let dest = self.target.join(&path);
+                    self.put(&staged.next().unwrap(), &dest)?;
+                    built.files.push(path);
+                }
+            }
+        }
+        Ok(built)
+    }
+
+    /// Write one more file, outside of the plan, as part of the transaction.
+    pub fn write_file(&mut self, path: &Path, bytes: &[u8]) -> Result<(), io::Error> {
+        self.write_file_with_mode(path, bytes, None)
+    }
+
+    /// Like [`Transaction::write_file`], giving the file `mode` if set.
+    pub fn write_file_with_mode(
+        &mut self,
+        path: &Path,
+        bytes: &[u8],
+        mode: Option<u32>,
+    ) -> Result<(), io::Error> {
+        let staged = self.stage(path)?;
+        fs::write(&staged, bytes)?;
+        if let Some(mode) = mode {
+            profile::set_mode(&staged, mode)?;
+        }
+        self.put(&staged, &self.target.join(path))
+    }
+
+    /// Create a symbolic link to `target`, as part of the transaction.
+    pub fn write_link(&mut self, path: &Path, target: &str) -> Result<(), io::Error> {
+        let staged = self.stage(path)?;
+        let link = FileInfo::new("", FileType::Link, FileContent::Link(target.to_string()));
+        link.write(&staged.to_string_lossy())?;
+        self.put(&staged, &self.target.join(path))
+    }
+
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// Create a dir and its missing parents, as part of the transaction.
+    pub fn create_dir(&mut self, path: &Path) -> Result<(), io::Error> {
+        self.create_dirs(&self.target.join(path))
+    }
+
+    /// Remove a file, keeping it in the staging dir until the transaction
+    /// is finished.
+    pub fn remove_file(&mut self, path: &Path) -> Result<(), io::Error> {
+        let removed = self.staging.join(format!("removed-{}", self.journal.len()));
+        self.move_file(&self.target.join(path), &removed)
+    }
+
+    /// Remove an empty dir.
+    pub fn remove_dir(&mut self, path: &Path) -> Result<(), io::Error> {
+        let path = self.target.join(path);
+        fs::remove_dir(&path)?;
+        self.journal.push(Undo::CreateDir(path));
+        Ok(())
+    }
+
+    /// Move a file within the target.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> Result<(), io::Error> {
+        self.move_file(&self.target.join(from), &self.target.join(to))
+    }
+
+    /// Undo everything this transaction did to the target.
+    pub fn rollback(mut self) -> Result<(), io::Error> {
+        let mut first_err = None;
+        while let Some(undo) = self.journal.pop() {
+            let result = match &undo {
+                Undo::RemoveDir(path) => fs::remove_dir(path),
+                Undo::CreateDir(path) => fs::create_dir(path),
+                Undo::RemoveStaging(path) => fs::remove_dir_all(path),
+                Undo::RemoveFile(path) => fs::remove_file(path),
+                Undo::Move { from, to } => fs::rename(from, to),
+            };
+            if let Err(err) = result {
+                first_err.get_or_insert(err);
+            }
+        }
+        match first_err {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Keep the changes and clean up the staging dir.
+    pub fn finish(self) -> Result<(), io::Error> {
+        fs::remove_dir_all(&self.staging)
+    }
+
+    /// Like `fs::create_dir_all`, but journals every dir it creates.
+    fn create_dirs(&mut self, path: &Path) -> Result<(), io::Error> {
+        let mut missing = vec![];
+        let mut p = path;
+        while !p.as_os_str().is_empty() && fs::symlink_metadata(p).is_err() {
+            missing.push(p.to_path_buf());
+            p = match p.parent() {
+                Some(parent) => parent,
+                None => break,
+            };
+        }
+
+        for dir in missing.into_iter().rev() {
+            fs::create_dir(&dir)?;
+            self.journal.push(Undo::RemoveDir(dir));
+        }
+        Ok(())
+    }
+
+    /// Where to stage a file for `path`, creating the dirs it goes into.
+    fn stage(&mut self, path: &Path) -> Result<PathBuf, io::Error> {
+        if let Some(parent) = self.target.join(path).parent() {
+            self.create_dirs(parent)?;
+        }
+        Ok(self.staging.join(format!("extra-{}", self.journal.len())))
+    }
+
+    fn move_file(&mut self, from: &Path, to: &Path) -> Result<(), io::Error> {
+        fs::rename(from, to)?;
+        self.journal.push(Undo::Move {
+            from: to.to_path_buf(),
+            to: from.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    /// Move a staged file to `dest`, keeping whatever it replaces in the
+    /// staging dir until the transaction is finished.
+    fn put(&mut self, staged: &Path, dest: &Path) -> Result<(), io::Error> {
+        if fs::symlink_metadata(dest).is_ok() {
+            let mut replaced = staged.as_os_str().to_owned();
+            replaced.push(".replaced");
+            self.move_file(dest, Path::new(&replaced))?;
+        }
+        fs::rename(staged, dest)?;
+        self.journal.push(Undo::RemoveFile(dest.to_path_buf()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::plan;
+    use profile::DirRoot;
+
+    fn text(name: &str, s: &str) -> FileInfo {
+        FileInfo::new(name, FileType::Text, FileContent::Text(s.to_string()))
+    }
+
+    #[test]
+    fn failed_build_leaves_target_as_it_was() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path();
+        fs::create_dir_all(target.join("docs")).unwrap();
+        fs::write(target.join("docs/README.md"), "mine").unwrap();
+
+        let mut root = DirRoot::new("");
+        let mut docs = DirRoot::new("docs");
+        docs.files.push(text("README.md", "theirs"));
+        docs.dirs.push(DirRoot::new("api"));
+        let mut src = DirRoot::new("src");
+        src.files.push(text("main.rs", "fn main() {}"));
+        root.dirs.push(docs);
+        root.dirs.push(src);
+
+        let mut steps = plan(&root, target);
+        for step in &mut steps {
+            step.action = Action::Write;
+        }
+        // Gets in the way of src/ after planning, so the commit fails after
+        // docs/ has already been written.
+        fs::write(target.join("src"), "").unwrap();
+
+        let mut tx = Transaction::begin(target).unwrap();
+        assert!(tx.apply(&steps).is_err());
+        tx.rollback().unwrap();
+
+        let mut left: Vec<_> = fs::read_dir(target)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["docs", "src"]);
+        assert!(!target.join("docs/api").exists());
+        assert_eq!(
+            fs::read_to_string(target.join("docs/README.md")).unwrap(),
+            "mine"
+        );
+    }
+}
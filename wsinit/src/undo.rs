@@ -0,0 +1,275 @@
+//! Take a recorded build back out of a workspace.
+//!
+//! Only what the build created is removed: the files it wrote where there
+//! was nothing, the `.new` files it wrote next to existing ones, and the dirs
+//! it created once they are empty again. Files it backed up are put back.
+//! Files it overwrote without a backup can't be restored and are left.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::manifest::{self, Build, How, Manifest};
+
+#[derive(Default)]
+pub struct UndoPlan {
+    /// Files to remove.
+    pub remove: Vec<String>,
+    /// Backups to move back, as `(backup, path)`.
+    pub restore: Vec<(String, String)>,
+    /// Files the build wrote that changed since, in `remove` or `restore`.
+    pub modified: Vec<String>,
+    /// Files the build wrote that are gone already.
+    pub missing: Vec<String>,
+    /// Files the build overwrote without a backup, left as they are.
+    pub overwritten: Vec<String>,
+    /// Files a later build wrote again, left as they are, with its id.
+    pub shared: Vec<(String, u32)>,
+    /// Dirs the build created, deepest first.
+    pub dirs: Vec<String>,
+}
+
+/// Work out what undoing `build` does to `workspace`.
+pub fn plan_undo(
+    manifest: &Manifest,
+    build: &Build,
+    workspace: &Path,
+) -> Result<UndoPlan, io::Error> {
+    let mut plan = UndoPlan::default();
+    let later: Vec<&Build> = manifest
+        .live()
+        .into_iter()
+        .filter(|b| b.id > build.id)
+        .collect();
+
+    for f in &build.files {
+        let path = match (f.how, &f.written_to) {
+            (How::Renamed, Some(path)) => path,
+            (How::Created | How::Overwritten, _) => &f.path,
+            _ => continue,
+        };
+        let wrote = |g: &&manifest::FileRecord| {
+            g.path == *path && !matches!(g.how, How::Unchanged | How::Skipped)
+        };
+        if let Some(b) = later.iter().find(|b| b.files.iter().any(|g| wrote(&g))) {
+            plan.shared.push((path.clone(), b.id));
+            continue;
+        }
+        if f.how == How::Overwritten && f.backup.is_none() {
+            plan.overwritten.push(path.clone());
+            continue;
+        }
+
+        for p in [Some(path), f.backup.as_ref()].into_iter().flatten() {
+            check_no_links(workspace, Path::new(p).parent().unwrap_or(Path::new("")))?;
+        }
+        match profile::read_entry(&workspace.join(path)) {
+            Ok(bytes) => {
+                if manifest::hash(&bytes) != f.hash {
+                    plan.modified.push(path.clone());
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                plan.missing.push(path.clone());
+                if f.backup.is_none() {
+                    continue;
+                }
+            }
+            Err(err) => return Err(err),
+        }
+        match &f.backup {
+            Some(backup) => plan.restore.push((backup.clone(), path.clone())),
+            None => plan.remove.push(path.clone()),
+        }
+    }
+
+    for dir in &build.dirs {
+        check_no_links(workspace, Path::new(dir))?;
+    }
+    plan.dirs = build.dirs.clone();
+    plan.dirs
+        .sort_by_key(|d| std::cmp::Reverse(d.matches('/').count()));
+    Ok(plan)
+}
+
+/// Refuse `dir` if it, or a dir leading to it, is a link in `workspace`:
+/// undoing would follow it out of the workspace.
+fn check_no_links(workspace: &Path, dir: &Path) -> Result<(), io::Error> {
+    let mut at = PathBuf::new();
+    for c in dir.components() {
+        at.push(c);
+        match fs::symlink_metadata(workspace.join(&at)) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is a link, undo does not follow it", at.display()),
+                ))
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Whether `dir` exists and has nothing in it.
+pub fn is_empty_dir(dir: &Path) -> bool {
+    fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::FileRecord;
+
+    fn record(path: &str, content: &str, how: How) -> FileRecord {
+        FileRecord {
+            path: path.to_string(),
+            hash: manifest::hash(content.as_bytes()),
+            how,
+            backup: None,
+            written_to: None,
+        }
+    }
+
+    fn build(id: u32, files: Vec<FileRecord>) -> Build {
+        let mut build = Build::empty();
+        build.id = id;
+        build.files = files;
+        build
+    }
+
+    #[test]
+    fn plans_what_to_take_back() {
+        let workspace = tempfile::tempdir().unwrap();
+        let ws = workspace.path();
+        fs::create_dir_all(ws.join("src/bin")).unwrap();
+        fs::write(ws.join("src/main.rs"), "main").unwrap();
+        fs::write(ws.join("src/lib.rs"), "changed").unwrap();
+        fs::write(ws.join("Makefile"), "make").unwrap();
+        fs::write(ws.join("Makefile.orig"), "old make").unwrap();
+        fs::write(ws.join("config.new"), "config").unwrap();
+        fs::write(ws.join("README"), "readme").unwrap();
+
+        let mut first = build(
+            1,
+            vec![
+                record("src/main.rs", "main", How::Created),
+                record("src/lib.rs", "lib", How::Created),
+                record("gone", "gone", How::Created),
+                FileRecord {
+                    backup: Some("Makefile.orig".to_string()),
+                    ..record("Makefile", "make", How::Overwritten)
+                },
+                FileRecord {
+                    written_to: Some("config.new".to_string()),
+                    ..record("config", "config", How::Renamed)
+                },
+                record("LICENSE", "mit", How::Overwritten),
+                record("README", "readme", How::Created),
+                record("kept", "kept", How::Skipped),
+                record("same", "same", How::Unchanged),
+            ],
+        );
+        first.dirs = vec!["src".to_string(), "src/bin".to_string()];
+        let second = build(2, vec![record("README", "readme", How::Overwritten)]);
+        let manifest = Manifest {
+            builds: vec![first, second],
+        };
+
+        let plan = plan_undo(&manifest, &manifest.builds[0], ws).unwrap();
+        assert_eq!(plan.remove, ["src/main.rs", "src/lib.rs", "config.new"]);
+        assert_eq!(
+            plan.restore,
+            [("Makefile.orig".to_string(), "Makefile".to_string())]
+        );
+        assert_eq!(plan.modified, ["src/lib.rs"]);
+        assert_eq!(plan.missing, ["gone"]);
+        assert_eq!(plan.overwritten, ["LICENSE"]);
+        assert_eq!(plan.shared, [("README".to_string(), 2)]);
+        assert_eq!(plan.dirs, ["src/bin", "src"]);
+    }
+
+    #[test]
+    fn restores_backups_of_deleted_files() {
+        let workspace = tempfile::tempdir().unwrap();
+        fs::write(workspace.path().join("Makefile.orig"), "old make").unwrap();
+        let manifest = Manifest {
+            builds: vec![build(
+                1,
+                vec![FileRecord {
+                    backup: Some("Makefile.orig".to_string()),
+                    ..record("Makefile", "make", How::Overwritten)
+                }],
+            )],
+        };
+
+        let plan = plan_undo(&manifest, &manifest.builds[0], workspace.path()).unwrap();
+        assert_eq!(plan.missing, ["Makefile"]);
+        assert_eq!(
+            plan.restore,
+            [("Makefile.orig".to_string(), "Makefile".to_string())]
+        );
+        assert!(plan.remove.is_empty());
+    }
+
+    #[test]
+    fn files_a_later_build_left_alone_are_not_shared() {
+        let workspace = tempfile::tempdir().unwrap();
+        fs::write(workspace.path().join("a"), "a").unwrap();
+        let manifest = Manifest {
+            builds: vec![
+                build(1, vec![record("a", "a", How::Created)]),
+                build(2, vec![record("a", "a", How::Unchanged)]),
+            ],
+        };
+
+        let plan = plan_undo(&manifest, &manifest.builds[0], workspace.path()).unwrap();
+        assert!(plan.shared.is_empty());
+        assert_eq!(plan.remove, ["a"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn does_not_follow_links_out_of_the_workspace() {
+        let workspace = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let ws = workspace.path();
+        fs::write(outside.path().join("authorized_keys"), "keys").unwrap();
+        fs::write(ws.join("payload"), "payload").unwrap();
+        std::os::unix::fs::symlink(outside.path(), ws.join("link")).unwrap();
+
+        let manifest = Manifest {
+            builds: vec![build(
+                1,
+                vec![FileRecord {
+                    backup: Some("payload".to_string()),
+                    ..record("link/authorized_keys", "keys", How::Overwritten)
+                }],
+            )],
+        };
+        assert!(plan_undo(&manifest, &manifest.builds[0], ws).is_err());
+
+        let manifest = Manifest {
+            builds: vec![build(
+                1,
+                vec![FileRecord {
+                    backup: Some("link/authorized_keys".to_string()),
+                    ..record("payload", "payload", How::Overwritten)
+                }],
+            )],
+        };
+        assert!(plan_undo(&manifest, &manifest.builds[0], ws).is_err());
+
+        let mut dirs = build(1, vec![]);
+        dirs.dirs = vec!["link".to_string()];
+        let manifest = Manifest { builds: vec![dirs] };
+        assert!(plan_undo(&manifest, &manifest.builds[0], ws).is_err());
+        assert_eq!(
+            fs::read_to_string(outside.path().join("authorized_keys")).unwrap(),
+            "keys"
+        );
+    }
+}
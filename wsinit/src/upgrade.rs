@@ -0,0 +1,328 @@
+//! Bring a workspace built from one version of a profile up to another.
+//!
+//! The rendered profile a build wrote is kept in the workspace as the merge
+//! base. Each file is then merged three ways: what the base had, what the
+//! workspace has now ("ours"), and what the new profile has ("theirs").
+//! Changes only one side made are taken as they are, text files changed on
+//! both sides are merged line by line, and what can't be merged is left with
+//! conflict markers, or next to the file as `<name>.rej`.
+
+use std::{fs, io, path::Path};
+
+use profile::{DirRoot, FileInfo};
+
+use crate::{
+    manifest::{self, How},
+    merge::merge3,
+    plan,
+};
+
+pub const REJECT_SUFFIX: &str = ".rej";
+
+/// What to do to the file in the workspace.
+pub enum Op {
+    Keep,
+    Write(Vec<u8>),
+    Remove,
+    /// Keep the file, write the profile's version to `<name>.rej`.
+    Reject(Vec<u8>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Nothing to do, the profile did not change it or the workspace
+    /// already has the new content.
+    Unchanged,
+    /// New in the profile.
+    Added,
+    /// Changed in the profile only.
+    Updated,
+    /// Changed on both sides, merged without conflicts.
+    Merged,
+    /// Changed on both sides in a way that needs a hand.
+    Conflict,
+    /// Removed from the profile, and not changed here.
+    Removed,
+    /// Removed from the profile, but changed here so kept.
+    KeptChanged,
+    /// Changed in the profile, but deleted here so not restored.
+    KeptDeleted,
+}
+
+pub struct Change {
+    /// `/` separated, relative to the workspace.
+    pub path: String,
+    pub outcome: Outcome,
+    pub op: Op,
+    /// sha256 of the new profile's content, if it has the file.
+    pub hash: Option<String>,
+    /// Permission bits to write the file with: the new profile's, or else
+    /// the ones the file has in the workspace.
+    pub mode: Option<u32>,
+    /// The new profile has a symbolic link here, `Op::Write` is its target.
+    pub link: bool,
+}
+
+impl Change {
+    /// How the manifest records the file, `None` if the new profile does
+    /// not have it.
+    pub fn how(&self) -> Option<How> {
+        match self.outcome {
+            Outcome::Unchanged => Some(How::Unchanged),
+            Outcome::Added => Some(How::Created),
+            Outcome::Updated => Some(How::Overwritten),
+            Outcome::Merged => Some(How::Merged),
+            Outcome::Conflict => Some(How::Conflicted),
+            Outcome::KeptDeleted => Some(How::Skipped),
+            Outcome::Removed | Outcome::KeptChanged => None,
+        }
+    }
+}
+
+/// Work out what upgrading `workspace` from `base` to `theirs` does to
+/// every file either of them has.
+pub fn plan_upgrade(
+    base: &DirRoot,
+    theirs: &DirRoot,
+    workspace: &Path,
+) -> Result<Vec<Change>, io::Error> {
+    let base = plan::files(base);
+    let theirs = plan::files(theirs);
+    let mut paths: Vec<&String> = base.keys().chain(theirs.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut changes = vec![];
+    for path in paths {
+        let ours = match profile::read_entry(&workspace.join(path)) {
+            Ok(it) => Some(it),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        let mode = match (theirs.get(path).and_then(|f| f.mode), &ours) {
+            (Some(mode), _) => Some(mode),
+            (None, Some(_)) => {
+                let meta = fs::symlink_metadata(workspace.join(path))?;
+                match meta.file_type().is_symlink() {
+                    true => None,
+                    false => profile::mode_of(&meta),
+                }
+            }
+            (None, None) => None,
+        };
+        let link = |f: Option<&&FileInfo>| f.is_some_and(|f| f.is_link());
+        let is_link = link(theirs.get(path));
+        let links = is_link || link(base.get(path));
+        let base = base.get(path).map(|f| f.as_bytes());
+        let theirs = theirs.get(path).map(|f| f.as_bytes());
+        let (outcome, op) = match decide(base, ours.as_deref(), theirs) {
+            // Link targets are not merged line by line.
+            (Outcome::Merged | Outcome::Conflict, Op::Write(_)) if links => (
+                Outcome::Conflict,
+                Op::Reject(theirs.unwrap_or_default().to_vec()),
+            ),
+            it => it,
+        };
+        changes.push(Change {
+            path: path.clone(),
+            outcome,
+            op,
+            hash: theirs.map(manifest::hash),
+            mode,
+            link: is_link,
+        });
+    }
+    Ok(changes)
+}
+
+fn decide(base: Option<&[u8]>, ours: Option<&[u8]>, theirs: Option<&[u8]>) -> (Outcome, Op) {
+    if base == theirs || ours == theirs {
+        return (Outcome::Unchanged, Op::Keep);
+    }
+    if ours == base {
+        return match theirs {
+            Some(theirs) => match base {
+                None => (Outcome::Added, Op::Write(theirs.to_vec())),
+                Some(_) => (Outcome::Updated, Op::Write(theirs.to_vec())),
+            },
+            None => (Outcome::Removed, Op::Remove),
+        };
+    }
+
+    match (base, ours, theirs) {
+        (_, Some(_), None) => (Outcome::KeptChanged, Op::Keep),
+        (Some(_), None, Some(_)) => (Outcome::KeptDeleted, Op::Keep),
+        (Some(base), Some(ours), Some(theirs)) => {
+            match (
+                std::str::from_utf8(base),
+                std::str::from_utf8(ours),
+                std::str::from_utf8(theirs),
+            ) {
+                (Ok(base), Ok(ours), Ok(theirs)) => {
+                    let merged = merge3(base, ours, theirs);
+                    let outcome = match merged.conflicts {
+                        0 => Outcome::Merged,
+                        _ => Outcome::Conflict,
+                    };
+                    (outcome, Op::Write(merged.text.into_bytes()))
+                }
+                _ => (Outcome::Conflict, Op::Reject(theirs.to_vec())),
+            }
+        }
+        // Added on both sides with different content.
+        (_, _, Some(theirs)) => (Outcome::Conflict, Op::Reject(theirs.to_vec())),
+        (_, None, None) => (Outcome::Unchanged, Op::Keep),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use profile::{FileContent, FileType};
+
+    fn text(name: &str, content: &str) -> FileInfo {
+        FileInfo::new(name, FileType::Text, FileContent::Text(content.to_string()))
+    }
+
+    fn root(files: Vec<FileInfo>) -> DirRoot {
+        let mut root = DirRoot::new("");
+        root.files = files;
+        root
+    }
+
+    fn op(op: &Op) -> String {
+        match op {
+            Op::Keep => "keep".to_string(),
+            Op::Write(bytes) => format!("write {}", String::from_utf8_lossy(bytes)),
+            Op::Remove => "remove".to_string(),
+            Op::Reject(bytes) => format!("reject {}", String::from_utf8_lossy(bytes)),
+        }
+    }
+
+    fn find<'a>(changes: &'a [Change], path: &str) -> &'a Change {
+        changes.iter().find(|c| c.path == path).unwrap()
+    }
+
+    #[test]
+    fn decides_each_file() {
+        let workspace = tempfile::tempdir().unwrap();
+        let ours = [
+            ("same", "a\n"),
+            ("updated", "a\n"),
+            ("merged", "a\nb\nc\nd\n"),
+            ("conflict", "a\nours\n"),
+            ("removed", "a\n"),
+            ("kept_changed", "mine\n"),
+            ("added_both", "mine\n"),
+            ("already", "new\n"),
+        ];
+        for (path, content) in ours {
+            fs::write(workspace.path().join(path), content).unwrap();
+        }
+        let base = root(vec![
+            text("same", "a\n"),
+            text("updated", "a\n"),
+            text("merged", "a\nb\nc\nd\n"),
+            text("conflict", "a\nb\n"),
+            text("removed", "a\n"),
+            text("kept_changed", "a\n"),
+            text("kept_deleted", "a\n"),
+            text("already", "old\n"),
+        ]);
+        let theirs = root(vec![
+            text("same", "a\n"),
+            text("updated", "b\n"),
+            text("merged", "a\nb\nc\nD\n"),
+            text("conflict", "a\ntheirs\n"),
+            text("kept_deleted", "b\n"),
+            text("added", "new\n"),
+            text("added_both", "theirs\n"),
+            text("already", "new\n"),
+        ]);
+        fs::write(workspace.path().join("merged"), "A\nb\nc\nd\n").unwrap();
+
+        let changes = plan_upgrade(&base, &theirs, workspace.path()).unwrap();
+        let expected = [
+            ("same", Outcome::Unchanged, "keep"),
+            ("updated", Outcome::Updated, "write b\n"),
+            ("merged", Outcome::Merged, "write A\nb\nc\nD\n"),
+            (
+                "conflict",
+                Outcome::Conflict,
+                "write a\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n",
+            ),
+            ("removed", Outcome::Removed, "remove"),
+            ("kept_changed", Outcome::KeptChanged, "keep"),
+            ("kept_deleted", Outcome::KeptDeleted, "keep"),
+            ("added", Outcome::Added, "write new\n"),
+            ("added_both", Outcome::Conflict, "reject theirs\n"),
+            ("already", Outcome::Unchanged, "keep"),
+        ];
+        assert_eq!(changes.len(), expected.len());
+        for (path, outcome, expected) in expected {
+            let change = find(&changes, path);
+            assert!(change.outcome == outcome, "{}", path);
+            assert_eq!(op(&change.op), expected, "{}", path);
+        }
+        assert!(find(&changes, "removed").hash.is_none());
+        assert_eq!(find(&changes, "updated").hash, Some(manifest::hash(b"b\n")));
+    }
+
+    #[test]
+    fn binary_files_are_not_merged() {
+        let workspace = tempfile::tempdir().unwrap();
+        fs::write(workspace.path().join("logo"), b"\xffours").unwrap();
+        let bin = |content: &[u8]| {
+            FileInfo::new("logo", FileType::Bin, FileContent::Bin(content.to_vec()))
+        };
+        let base = root(vec![bin(b"\xffbase")]);
+        let theirs = root(vec![bin(b"\xfftheirs")]);
+
+        let changes = plan_upgrade(&base, &theirs, workspace.path()).unwrap();
+        assert!(changes[0].outcome == Outcome::Conflict);
+        assert!(matches!(&changes[0].op, Op::Reject(b) if b == b"\xfftheirs"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_are_rejected_not_merged() {
+        let workspace = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink("ours", workspace.path().join(".envrc")).unwrap();
+        let link = |target: &str| {
+            FileInfo::new(
+                ".envrc",
+                FileType::Link,
+                FileContent::Link(target.to_string()),
+            )
+        };
+        let base = root(vec![link("base")]);
+        let theirs = root(vec![link("theirs")]);
+
+        let changes = plan_upgrade(&base, &theirs, workspace.path()).unwrap();
+        assert!(changes[0].outcome == Outcome::Conflict);
+        assert_eq!(op(&changes[0].op), "reject theirs");
+        assert!(changes[0].link);
+        assert_eq!(changes[0].mode, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn takes_the_mode_of_the_profile_or_else_the_workspace() {
+        let workspace = tempfile::tempdir().unwrap();
+        for path in ["run", "notes"] {
+            let path = workspace.path().join(path);
+            fs::write(&path, "a\n").unwrap();
+            profile::set_mode(&path, 0o640).unwrap();
+        }
+        let mut run = text("run", "b\n");
+        run.mode = Some(0o755);
+        let base = root(vec![text("run", "a\n"), text("notes", "a\n")]);
+        let theirs = root(vec![run, text("notes", "b\n"), text("new", "b\n")]);
+
+        let changes = plan_upgrade(&base, &theirs, workspace.path()).unwrap();
+        assert_eq!(find(&changes, "run").mode, Some(0o755));
+        assert_eq!(find(&changes, "notes").mode, Some(0o640));
+        assert_eq!(find(&changes, "new").mode, None);
+        assert!(!find(&changes, "run").link);
+    }
+}